@@ -0,0 +1,784 @@
+use bson::{doc, Bson, Document};
+
+use crate::{
+    database::Database,
+    filter::{as_integer, as_number, get_path_values, matches_filter, validate_filter, Filter},
+    ordering::{compare_bson, sort_documents, validate_sort, values_equal},
+    projection::{apply_projection, is_truthy, validate_projection},
+    update::{get_path, set_path},
+};
+
+const ACCUMULATORS: [&str; 8] = [
+    "$sum", "$avg", "$min", "$max", "$push", "$first", "$last", "$count",
+];
+
+/// Checks every stage of the pipeline before running it, so `run_pipeline`
+/// only has to deal with well formed stages.
+pub fn validate_pipeline(stages: &[Document]) -> Result<(), String> {
+    for stage in stages.iter() {
+        if stage.len() != 1 {
+            return Err("Each stage must have exactly one field".to_string());
+        }
+        let (name, spec) = match stage.iter().next() {
+            Some(stage) => stage,
+            None => return Err("Empty stage".to_string()),
+        };
+        match (name.as_str(), spec) {
+            ("$match", Bson::Document(filter)) => validate_filter(filter)?,
+            ("$project", Bson::Document(projection)) => validate_project(projection)?,
+            ("$group", Bson::Document(group)) => validate_group(group)?,
+            ("$sort", Bson::Document(sort)) => validate_sort(sort)?,
+            ("$limit", value) | ("$skip", value) => {
+                stage_count(name, value)?;
+            }
+            ("$unwind", spec) => {
+                unwind_options(spec)?;
+            }
+            ("$lookup", Bson::Document(lookup)) => validate_lookup(lookup)?,
+            ("$match", _) | ("$project", _) | ("$group", _) | ("$sort", _) | ("$lookup", _) => {
+                return Err(format!("'{}' needs an object", name));
+            }
+            _ => return Err(format!("Unknown stage '{}'", name)),
+        }
+    }
+
+    return Ok(());
+}
+
+/// Runs the documents through every stage of the pipeline in order. The
+/// database gives `$lookup` access to the other collections. A stage can still
+/// fail after validation when the `let` variables of a `$lookup` make it invalid.
+pub fn run_pipeline(
+    documents: Vec<Document>,
+    stages: &[Document],
+    database: &Database,
+) -> Result<Vec<Document>, String> {
+    let mut documents = documents;
+    for stage in stages.iter() {
+        let (name, spec) = match stage.iter().next() {
+            Some(stage) => stage,
+            None => continue,
+        };
+        documents = match (name.as_str(), spec) {
+            ("$match", Bson::Document(filter)) => {
+                let filter = Filter::new(filter.clone())?;
+                documents
+                    .into_iter()
+                    .filter(|doc| filter.matches(doc))
+                    .collect()
+            }
+            ("$project", Bson::Document(projection)) => documents
+                .iter()
+                .map(|doc| project_document(doc, projection))
+                .collect(),
+            ("$group", Bson::Document(group)) => group_documents(&documents, group),
+            ("$sort", Bson::Document(sort)) => {
+                sort_documents(&mut documents, sort);
+                documents
+            }
+            ("$limit", value) => {
+                let limit = stage_count(name, value)?;
+                documents.into_iter().take(limit).collect()
+            }
+            ("$skip", value) => {
+                let skip = stage_count(name, value)?;
+                documents.into_iter().skip(skip).collect()
+            }
+            ("$unwind", spec) => unwind_documents(documents, &unwind_options(spec)?),
+            ("$lookup", Bson::Document(lookup)) => {
+                let foreign = lookup_collection(lookup, database);
+                documents
+                    .into_iter()
+                    .map(|doc| lookup_document(doc, lookup, foreign, database))
+                    .collect::<Result<Vec<Document>, String>>()?
+            }
+            _ => documents,
+        };
+    }
+
+    return Ok(documents);
+}
+
+/// Reads the operand of `$limit` or `$skip`.
+fn stage_count(name: &str, value: &Bson) -> Result<usize, String> {
+    match as_integer(value) {
+        Some(number) if number >= 0 => return Ok(number as usize),
+        _ => return Err(format!("'{}' needs a non negative integer", name)),
+    }
+}
+
+/// Evaluates an aggregation expression against a document: "$path" strings
+/// read a field, objects evaluate each of their fields, anything else is a literal.
+pub fn evaluate_expression(doc: &Document, expression: &Bson) -> Bson {
+    match expression {
+        Bson::String(path) if path.starts_with('$') => {
+            return resolve_field_path(&Bson::Document(doc.clone()), &path[1..])
+                .unwrap_or(Bson::Null)
+        }
+        Bson::Document(fields) => {
+            if let Some(literal) = fields.get("$literal") {
+                return literal.clone();
+            }
+            let mut evaluated = Document::new();
+            for (key, value) in fields.iter() {
+                evaluated.insert(key, evaluate_expression(doc, value));
+            }
+            return Bson::Document(evaluated);
+        }
+        Bson::Array(items) => {
+            return Bson::Array(
+                items
+                    .iter()
+                    .map(|item| evaluate_expression(doc, item))
+                    .collect(),
+            )
+        }
+        _ => return expression.clone(),
+    }
+}
+
+/// Reads a dotted field path. Arrays along the path produce an array with the
+/// value of the rest of the path in each element.
+fn resolve_field_path(value: &Bson, path: &str) -> Option<Bson> {
+    if path.is_empty() {
+        return Some(value.clone());
+    }
+
+    let (head, rest) = match path.split_once('.') {
+        Some((head, rest)) => (head, rest),
+        None => (path, ""),
+    };
+
+    match value {
+        Bson::Document(doc) => return resolve_field_path(doc.get(head)?, rest),
+        Bson::Array(items) => {
+            let values: Vec<Bson> = items
+                .iter()
+                .filter_map(|item| match item {
+                    Bson::Document(_) => resolve_field_path(item, path),
+                    _ => None,
+                })
+                .collect();
+            return Some(Bson::Array(values));
+        }
+        _ => return None,
+    }
+}
+
+fn is_computed(value: &Bson) -> bool {
+    match value {
+        Bson::String(_) | Bson::Document(_) | Bson::Array(_) => return true,
+        _ => return false,
+    }
+}
+
+fn validate_project(projection: &Document) -> Result<(), String> {
+    let (plain, computed) = split_project(projection);
+    if !computed.is_empty() {
+        for (key, value) in plain.iter() {
+            if key != "_id" && !is_computed(value) && !is_truthy(value) {
+                return Err(format!("Cant exclude '{}' while computing new fields", key));
+            }
+        }
+    }
+
+    return validate_projection(&plain);
+}
+
+/// Separates the 0/1 and `$slice` fields of a `$project` stage, which behave
+/// like a find projection, from the fields computed by expressions.
+fn split_project(projection: &Document) -> (Document, Document) {
+    let mut plain = Document::new();
+    let mut computed = Document::new();
+    for (key, value) in projection.iter() {
+        let slice = matches!(value, Bson::Document(inner) if inner.contains_key("$slice"));
+        if slice || !is_computed(value) {
+            plain.insert(key, value.clone());
+        } else {
+            computed.insert(key, value.clone());
+        }
+    }
+
+    return (plain, computed);
+}
+
+/// Applies a `$project` stage. Computed fields turn it into an inclusion, as
+/// only the listed fields are kept next to the computed ones.
+fn project_document(doc: &Document, projection: &Document) -> Document {
+    let (plain, computed) = split_project(projection);
+    if computed.is_empty() {
+        return apply_projection(doc, &plain);
+    }
+
+    let mut included = Document::new();
+    let excludes_id = matches!(plain.get("_id"), Some(value) if !is_truthy(value));
+    included.insert("_id", if excludes_id { 0 } else { 1 });
+    for (key, value) in plain.iter() {
+        if key != "_id" {
+            included.insert(key, value.clone());
+        }
+    }
+
+    let mut projected = if excludes_id && included.len() == 1 {
+        Document::new()
+    } else {
+        apply_projection(doc, &included)
+    };
+    for (key, expression) in computed.iter() {
+        projected.insert(key, evaluate_expression(doc, expression));
+    }
+
+    return projected;
+}
+
+fn validate_group(group: &Document) -> Result<(), String> {
+    if !group.contains_key("_id") {
+        return Err("'$group' needs an '_id' field".to_string());
+    }
+
+    for (key, value) in group.iter() {
+        if key == "_id" {
+            continue;
+        }
+        let accumulator = match value {
+            Bson::Document(accumulator) if accumulator.len() == 1 => accumulator,
+            _ => return Err(format!("The field '{}' must be an accumulator", key)),
+        };
+        for name in accumulator.keys() {
+            if !ACCUMULATORS.contains(&name.as_str()) {
+                return Err(format!("Unknown accumulator '{}'", name));
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+struct Group {
+    key: Bson,
+    documents: Vec<Document>,
+}
+
+fn group_documents(documents: &[Document], spec: &Document) -> Vec<Document> {
+    let id_expression = spec.get("_id").cloned().unwrap_or(Bson::Null);
+    let mut groups: Vec<Group> = Vec::new();
+
+    for doc in documents.iter() {
+        let key = evaluate_expression(doc, &id_expression);
+        match groups
+            .iter_mut()
+            .find(|group| values_equal(&group.key, &key))
+        {
+            Some(group) => group.documents.push(doc.clone()),
+            None => groups.push(Group {
+                key,
+                documents: vec![doc.clone()],
+            }),
+        }
+    }
+
+    let mut results: Vec<Document> = Vec::new();
+    for group in groups {
+        let mut result = Document::new();
+        result.insert("_id", group.key.clone());
+        for (field, accumulator) in spec.iter() {
+            if field == "_id" {
+                continue;
+            }
+            if let Some((name, expression)) =
+                accumulator.as_document().and_then(|acc| acc.iter().next())
+            {
+                result.insert(field, accumulate(name, expression, &group.documents));
+            }
+        }
+        results.push(result);
+    }
+
+    return results;
+}
+
+fn accumulate(name: &str, expression: &Bson, documents: &[Document]) -> Bson {
+    let values: Vec<Bson> = documents
+        .iter()
+        .map(|doc| evaluate_expression(doc, expression))
+        .collect();
+
+    match name {
+        "$sum" => return sum_values(&values),
+        "$count" => return Bson::Int32(documents.len() as i32),
+        "$avg" => {
+            let numbers: Vec<f64> = values.iter().filter_map(as_number).collect();
+            if numbers.is_empty() {
+                return Bson::Null;
+            }
+            return Bson::Double(numbers.iter().sum::<f64>() / numbers.len() as f64);
+        }
+        "$min" => {
+            return values
+                .into_iter()
+                .filter(|value| *value != Bson::Null)
+                .min_by(compare_bson)
+                .unwrap_or(Bson::Null)
+        }
+        "$max" => {
+            return values
+                .into_iter()
+                .filter(|value| *value != Bson::Null)
+                .max_by(compare_bson)
+                .unwrap_or(Bson::Null)
+        }
+        "$push" => return Bson::Array(values),
+        "$first" => return values.into_iter().next().unwrap_or(Bson::Null),
+        "$last" => return values.into_iter().last().unwrap_or(Bson::Null),
+        _ => return Bson::Null,
+    }
+}
+
+/// Adds up the numeric values, ignoring the rest. The result stays an integer
+/// unless a double is involved or the total doesnt fit.
+fn sum_values(values: &[Bson]) -> Bson {
+    let mut integer_total: i64 = 0;
+    let mut double_total: f64 = 0.0;
+    let mut has_double = false;
+    let mut has_int64 = false;
+
+    for value in values.iter() {
+        let number = match value {
+            Bson::Int32(number) => *number as i64,
+            Bson::Int64(number) => {
+                has_int64 = true;
+                *number
+            }
+            Bson::Double(number) => {
+                double_total += number;
+                has_double = true;
+                continue;
+            }
+            _ => continue,
+        };
+        match integer_total.checked_add(number) {
+            Some(total) => integer_total = total,
+            None => {
+                // Past the range of Int64 the total is kept as a double
+                double_total += number as f64;
+                has_double = true;
+            }
+        }
+    }
+
+    if has_double {
+        return Bson::Double(double_total + integer_total as f64);
+    }
+    if !has_int64 && i32::try_from(integer_total).is_ok() {
+        return Bson::Int32(integer_total as i32);
+    }
+
+    return Bson::Int64(integer_total);
+}
+
+struct UnwindOptions {
+    path: String,
+    preserve_empty: bool,
+    index_field: Option<String>,
+}
+
+fn unwind_options(spec: &Bson) -> Result<UnwindOptions, String> {
+    let (path, options) = match spec {
+        Bson::String(path) => (path.clone(), Document::new()),
+        Bson::Document(options) => match options.get("path") {
+            Some(Bson::String(path)) => (path.clone(), options.clone()),
+            _ => return Err("'$unwind' needs a 'path' field".to_string()),
+        },
+        _ => return Err("'$unwind' needs a field path".to_string()),
+    };
+
+    if !path.starts_with('$') || path.len() < 2 {
+        return Err("The '$unwind' path must start with '$'".to_string());
+    }
+
+    let preserve_empty = match options.get("preserveNullAndEmptyArrays") {
+        Some(Bson::Boolean(preserve)) => *preserve,
+        Some(_) => return Err("'preserveNullAndEmptyArrays' must be a boolean".to_string()),
+        None => false,
+    };
+    let index_field = match options.get("includeArrayIndex") {
+        Some(Bson::String(field)) => Some(field.clone()),
+        Some(_) => return Err("'includeArrayIndex' must be a field name".to_string()),
+        None => None,
+    };
+
+    return Ok(UnwindOptions {
+        path: path[1..].to_string(),
+        preserve_empty,
+        index_field,
+    });
+}
+
+fn unwind_documents(documents: Vec<Document>, options: &UnwindOptions) -> Vec<Document> {
+    let mut unwound: Vec<Document> = Vec::new();
+    for doc in documents {
+        let value = get_path(&doc, &options.path).cloned();
+        match value {
+            Some(Bson::Array(items)) if !items.is_empty() => {
+                for (i, item) in items.into_iter().enumerate() {
+                    let mut copy = doc.clone();
+                    let _ = set_path(&mut copy, &options.path, item);
+                    if let Some(index_field) = &options.index_field {
+                        copy.insert(index_field, i as i64);
+                    }
+                    unwound.push(copy);
+                }
+            }
+            Some(Bson::Array(_)) | Some(Bson::Null) | None => {
+                if options.preserve_empty {
+                    let mut copy = doc.clone();
+                    if let Some(index_field) = &options.index_field {
+                        copy.insert(index_field, Bson::Null);
+                    }
+                    unwound.push(copy);
+                }
+            }
+            Some(_) => {
+                let mut copy = doc;
+                if let Some(index_field) = &options.index_field {
+                    copy.insert(index_field, Bson::Null);
+                }
+                unwound.push(copy);
+            }
+        }
+    }
+
+    return unwound;
+}
+
+fn validate_lookup(lookup: &Document) -> Result<(), String> {
+    for field in ["from", "as"] {
+        if !matches!(lookup.get(field), Some(Bson::String(_))) {
+            return Err(format!("'$lookup' needs the '{}' field", field));
+        }
+    }
+
+    let local_field = lookup.get("localField");
+    let foreign_field = lookup.get("foreignField");
+    match (local_field, foreign_field) {
+        (Some(Bson::String(_)), Some(Bson::String(_))) => {}
+        (None, None) if lookup.contains_key("pipeline") => {}
+        _ => {
+            return Err(
+                "'$lookup' needs 'localField' and 'foreignField', or a 'pipeline'".to_string(),
+            )
+        }
+    }
+
+    if let Some(variables) = lookup.get("let") {
+        if !matches!(variables, Bson::Document(_)) {
+            return Err("'let' must be an object of variables".to_string());
+        }
+    }
+
+    if let Some(pipeline) = lookup.get("pipeline") {
+        return validate_pipeline(&pipeline_stages(pipeline)?);
+    }
+
+    return Ok(());
+}
+
+fn pipeline_stages(pipeline: &Bson) -> Result<Vec<Document>, String> {
+    let items = match pipeline {
+        Bson::Array(items) => items,
+        _ => return Err("'pipeline' must be an array of stages".to_string()),
+    };
+
+    let mut stages: Vec<Document> = Vec::new();
+    for item in items.iter() {
+        match item {
+            Bson::Document(stage) => stages.push(stage.clone()),
+            _ => return Err("Each stage must be an object".to_string()),
+        }
+    }
+
+    return Ok(stages);
+}
+
+/// Returns the documents of the `from` collection of a `$lookup` stage, which
+/// is empty when the collection doesnt exist.
+fn lookup_collection<'a>(lookup: &Document, database: &'a Database) -> &'a [Document] {
+    let from = lookup.get_str("from").unwrap_or_default();
+    match database.find_collection(from) {
+        Some(collection) => return collection.get_collection(),
+        None => return &[],
+    }
+}
+
+/// Applies a `$lookup` stage to one document, adding the array of the foreign
+/// documents that match it.
+fn lookup_document(
+    doc: Document,
+    lookup: &Document,
+    foreign: &[Document],
+    database: &Database,
+) -> Result<Document, String> {
+    let target = lookup.get_str("as").unwrap_or_default();
+    let fields = (lookup.get_str("localField"), lookup.get_str("foreignField"));
+    let mut joined: Vec<Document> = match fields {
+        (Ok(local_field), Ok(foreign_field)) => {
+            let mut local_values: Vec<Bson> = Vec::new();
+            for value in get_path_values(&doc, local_field) {
+                match value {
+                    Bson::Array(items) => local_values.extend(items.iter().cloned()),
+                    _ => local_values.push(value.clone()),
+                }
+            }
+            if local_values.is_empty() {
+                local_values.push(Bson::Null);
+            }
+
+            let mut filter = Document::new();
+            filter.insert(foreign_field, doc! { "$in": local_values });
+            foreign
+                .iter()
+                .filter(|candidate| matches_filter(candidate, &filter))
+                .cloned()
+                .collect()
+        }
+        _ => foreign.to_vec(),
+    };
+
+    if let Some(pipeline) = lookup.get("pipeline") {
+        let mut stages = pipeline_stages(pipeline)?;
+        if let Some(Bson::Document(variables)) = lookup.get("let") {
+            let mut values = Document::new();
+            for (name, expression) in variables.iter() {
+                values.insert(name, evaluate_expression(&doc, expression));
+            }
+            stages = stages
+                .iter()
+                .map(|stage| replace_variables(stage, &values))
+                .collect();
+        }
+        joined = run_pipeline(joined, &stages, database)?;
+    }
+
+    let mut doc = doc;
+    let _ = set_path(&mut doc, target, Bson::from(joined));
+    return Ok(doc);
+}
+
+/// Replaces every "$$name" string in the stage with the value of the `let` variable.
+fn replace_variables(stage: &Document, values: &Document) -> Document {
+    let mut replaced = Document::new();
+    for (key, value) in stage.iter() {
+        replaced.insert(key, replace_variable_value(value, values));
+    }
+
+    return replaced;
+}
+
+fn replace_variable_value(value: &Bson, values: &Document) -> Bson {
+    match value {
+        Bson::String(name) if name.starts_with("$$") => match values.get(&name[2..]) {
+            Some(replacement) => return replacement.clone(),
+            None => return value.clone(),
+        },
+        Bson::Document(inner) => return Bson::Document(replace_variables(inner, values)),
+        Bson::Array(items) => {
+            return Bson::Array(
+                items
+                    .iter()
+                    .map(|item| replace_variable_value(item, values))
+                    .collect(),
+            )
+        }
+        _ => return value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, Bson, Document};
+
+    use super::{run_pipeline, sum_values, validate_pipeline};
+    use crate::{collection::Collection, database::Database};
+
+    fn run(documents: Vec<Document>, stages: Vec<Document>) -> Vec<Document> {
+        assert!(validate_pipeline(&stages).is_ok());
+        let database = Database::new("test".to_string(), Vec::new());
+        return run_pipeline(documents, &stages, &database).unwrap();
+    }
+
+    fn orders() -> Vec<Document> {
+        return vec![
+            doc! { "_id": 1, "customer": "ada", "total": 10, "items": ["a", "b"] },
+            doc! { "_id": 2, "customer": "bob", "total": 5, "items": [] },
+            doc! { "_id": 3, "customer": "ada", "total": 7.5, "items": ["c"] },
+        ];
+    }
+
+    #[test]
+    fn runs_stages_in_order() {
+        let stages = vec![
+            doc! { "$match": { "total": { "$gt": 5 } } },
+            doc! { "$project": { "customer": 1, "_id": 0 } },
+            doc! { "$sort": { "customer": 1 } },
+            doc! { "$skip": 1 },
+            doc! { "$limit": 5 },
+        ];
+        assert_eq!(run(orders(), stages), vec![doc! { "customer": "ada" }]);
+    }
+
+    #[test]
+    fn groups_documents() {
+        let group = doc! {
+            "_id": "$customer",
+            "total": { "$sum": "$total" },
+            "orders": { "$sum": 1 },
+            "average": { "$avg": "$total" },
+            "largest": { "$max": "$total" },
+            "ids": { "$push": "$_id" },
+        };
+        let results = run(orders(), vec![doc! { "$group": group }]);
+        let ada = doc! {
+            "_id": "ada",
+            "total": 17.5,
+            "orders": 2,
+            "average": 8.75,
+            "largest": 10,
+            "ids": [1, 3],
+        };
+        assert_eq!(results[0], ada);
+        assert_eq!(results[1].get("total"), Some(&Bson::Int32(5)));
+    }
+
+    #[test]
+    fn sums_past_the_range_of_integers() {
+        let int32 = vec![Bson::Int32(i32::MAX), Bson::Int32(1)];
+        assert_eq!(sum_values(&int32), Bson::Int64(i32::MAX as i64 + 1));
+        let int64 = vec![Bson::Int64(i64::MAX), Bson::Int32(1)];
+        assert_eq!(sum_values(&int64), Bson::Double(i64::MAX as f64 + 1.0));
+        let back = vec![Bson::Int64(i64::MAX), Bson::Int64(1), Bson::Int64(-1)];
+        assert_eq!(sum_values(&back), Bson::Double(i64::MAX as f64));
+        let ignored = vec![Bson::from("1"), Bson::Null, Bson::Int32(2)];
+        assert_eq!(sum_values(&ignored), Bson::Int32(2));
+        assert_eq!(sum_values(&[]), Bson::Int32(0));
+    }
+
+    #[test]
+    fn unwinds_arrays() {
+        let unwind = doc! { "$unwind": "$items" };
+        let project = doc! { "$project": { "items": 1 } };
+        let results = run(orders(), vec![unwind, project.clone()]);
+        let ids: Vec<i32> = results
+            .iter()
+            .map(|doc| doc.get_i32("_id").unwrap())
+            .collect();
+        assert_eq!(ids, vec![1, 1, 3]);
+
+        let unwind = doc! {
+            "$unwind": { "path": "$items", "preserveNullAndEmptyArrays": true, "includeArrayIndex": "i" }
+        };
+        let results = run(orders(), vec![unwind, doc! { "$match": { "_id": 2 } }]);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].get("i"), Some(&Bson::Null));
+    }
+
+    #[test]
+    fn rejects_invalid_pipelines() {
+        assert!(validate_pipeline(&[doc! { "$match": { "a": 1 }, "$limit": 1 }]).is_err());
+        assert!(validate_pipeline(&[doc! { "$out": "other" }]).is_err());
+        assert!(validate_pipeline(&[doc! { "$limit": -1 }]).is_err());
+        assert!(validate_pipeline(&[doc! { "$group": { "n": { "$sum": 1 } } }]).is_err());
+        let unknown = doc! { "$group": { "_id": null, "n": { "$median": "$a" } } };
+        assert!(validate_pipeline(&[unknown]).is_err());
+        assert!(validate_pipeline(&[doc! { "$unwind": "items" }]).is_err());
+        assert!(validate_pipeline(&[doc! { "$match": { "a": { "$bad": 1 } } }]).is_err());
+    }
+
+    fn database_with_products() -> Database {
+        let mut products = Collection::new("products".to_string());
+        for doc in [
+            doc! { "_id": "a", "price": 3 },
+            doc! { "_id": "b", "price": 8 },
+            doc! { "_id": "c", "price": 1 },
+        ] {
+            assert!(products.add_to_collection(doc).is_ok());
+        }
+        return Database::new("test".to_string(), vec![products]);
+    }
+
+    fn lookup_ids(results: &[Document], field: &str) -> Vec<Vec<String>> {
+        return results
+            .iter()
+            .map(|doc| {
+                doc.get_array(field)
+                    .unwrap()
+                    .iter()
+                    .map(|item| {
+                        item.as_document()
+                            .unwrap()
+                            .get_str("_id")
+                            .unwrap()
+                            .to_string()
+                    })
+                    .collect()
+            })
+            .collect();
+    }
+
+    #[test]
+    fn joins_by_equal_fields() {
+        let database = database_with_products();
+        let lookup = doc! {
+            "$lookup": { "from": "products", "localField": "items", "foreignField": "_id", "as": "products" }
+        };
+        let results = run_pipeline(orders(), &[lookup], &database).unwrap();
+        let expected = vec![vec!["a", "b"], vec![], vec!["c"]];
+        assert_eq!(lookup_ids(&results, "products"), expected);
+    }
+
+    #[test]
+    fn joins_through_a_pipeline() {
+        let database = database_with_products();
+        let lookup = doc! {
+            "$lookup": {
+                "from": "products",
+                "let": { "budget": "$total" },
+                "pipeline": [
+                    { "$match": { "price": { "$lte": "$$budget" } } },
+                    { "$sort": { "price": -1 } },
+                ],
+                "as": "affordable",
+            }
+        };
+        let results = run_pipeline(orders(), &[lookup], &database).unwrap();
+        let expected = vec![vec!["b", "a", "c"], vec!["a", "c"], vec!["a", "c"]];
+        assert_eq!(lookup_ids(&results, "affordable"), expected);
+    }
+
+    #[test]
+    fn joins_missing_collections_as_empty() {
+        let database = database_with_products();
+        let lookup = doc! {
+            "$lookup": { "from": "missing", "localField": "items", "foreignField": "_id", "as": "j" }
+        };
+        let results = run_pipeline(orders(), &[lookup], &database).unwrap();
+        assert!(results
+            .iter()
+            .all(|doc| doc.get_array("j").unwrap().is_empty()));
+    }
+
+    #[test]
+    fn fails_when_variables_make_a_stage_invalid() {
+        let database = database_with_products();
+        let lookup = doc! {
+            "$lookup": {
+                "from": "products",
+                "let": { "pattern": "(" },
+                "pipeline": [{ "$match": { "_id": { "$regex": "$$pattern" } } }],
+                "as": "j",
+            }
+        };
+        let stages = vec![lookup];
+        assert!(validate_pipeline(&stages).is_ok());
+        assert!(run_pipeline(orders(), &stages, &database).is_err());
+    }
+}
@@ -1,13 +1,423 @@
-use bson::Document;
+use bson::{oid::ObjectId, Bson, DateTime, Document};
 
-use serde_json::Value;
+use serde_json::{json, Map, Value};
 
-pub fn string_to_document(string: &str) -> Result<Document, String> {
-    match serde_json::from_str::<Value>(&string) {
-        Ok(json_value) => {
-            let bson_doc = bson::to_document(&json_value).expect("Failed");
-            return Ok(bson_doc);
+/// The keys of the MongoDB Extended JSON objects that stand for a single
+/// value, such as `{"$oid": "..."}` or `{"$date": "..."}`.
+const EXTENDED_JSON_KEYS: [&str; 16] = [
+    "$oid",
+    "$date",
+    "$numberInt",
+    "$numberLong",
+    "$numberDouble",
+    "$numberDecimal",
+    "$binary",
+    "$uuid",
+    "$regularExpression",
+    "$timestamp",
+    "$symbol",
+    "$code",
+    "$minKey",
+    "$maxKey",
+    "$undefined",
+    "$dbPointer",
+];
+
+/// Why a json value cant be read, and the byte of its text where the problem is.
+pub struct JsonError {
+    offset: usize,
+    message: String,
+}
+
+impl JsonError {
+    fn new(offset: usize, message: String) -> Self {
+        Self { offset, message }
+    }
+
+    pub fn get_offset(&self) -> usize {
+        return self.offset;
+    }
+
+    pub fn get_message(&self) -> String {
+        return self.message.to_owned();
+    }
+}
+
+pub fn string_to_document(string: &str) -> Result<Document, JsonError> {
+    match string_to_bson(string)? {
+        Bson::Document(document) => return Ok(document),
+        _ => return Err(JsonError::new(0, "Expected a json object".to_string())),
+    }
+}
+
+pub fn string_to_bson(string: &str) -> Result<Bson, JsonError> {
+    let (expanded, sources) = expand_shell_syntax(string)?;
+    match serde_json::from_str::<Value>(&expanded) {
+        Ok(json_value) => match json_to_bson(json_value) {
+            Ok(value) => return Ok(value),
+            Err(err) => return Err(JsonError::new(0, err)),
+        },
+        Err(err) => {
+            // serde_json counts the column in bytes, and the expanded text can
+            // be longer than the input
+            let line_start: usize = expanded
+                .split('\n')
+                .take(err.line().saturating_sub(1))
+                .map(|line| line.len() + 1)
+                .sum();
+            let offset = line_start + err.column().saturating_sub(1);
+            let offset = sources.get(offset).copied().unwrap_or(string.len());
+
+            let message = err.to_string();
+            let message = match message.rfind(" at line ") {
+                Some(end) => message[..end].to_string(),
+                None => message,
+            };
+            return Err(JsonError::new(offset, message));
+        }
+    }
+}
+
+/// Converts a json value into bson, reading the Extended JSON objects as the
+/// values they stand for.
+fn json_to_bson(value: Value) -> Result<Bson, String> {
+    match value {
+        Value::Object(map) => {
+            if map
+                .keys()
+                .any(|key| EXTENDED_JSON_KEYS.contains(&key.as_str()))
+            {
+                return extended_json_value(map);
+            }
+            let mut document = Document::new();
+            for (key, value) in map {
+                document.insert(key, json_to_bson(value)?);
+            }
+            return Ok(Bson::Document(document));
+        }
+        Value::Array(items) => {
+            let mut array: Vec<Bson> = Vec::new();
+            for item in items {
+                array.push(json_to_bson(item)?);
+            }
+            return Ok(Bson::Array(array));
+        }
+        _ => match bson::to_bson(&value) {
+            Ok(value) => return Ok(value),
+            Err(_e) => return Err("Error converting json".to_string()),
+        },
+    }
+}
+
+/// Reads an Extended JSON value in its canonical or relaxed form. `$date` also
+/// takes milliseconds since the epoch, and dates without a time or time zone.
+fn extended_json_value(map: Map<String, Value>) -> Result<Bson, String> {
+    if map.len() == 1 {
+        match map.get("$date") {
+            Some(Value::Number(millis)) => match millis.as_i64() {
+                Some(millis) => return Ok(Bson::DateTime(DateTime::from_millis(millis))),
+                None => return Err("'$date' needs whole milliseconds".to_string()),
+            },
+            Some(Value::String(date)) => match DateTime::parse_rfc3339_str(complete_date(date)) {
+                Ok(date) => return Ok(Bson::DateTime(date)),
+                Err(_e) => return Err(format!("Invalid date '{}'", date)),
+            },
+            _ => {}
+        }
+    }
+
+    match Bson::try_from(Value::Object(map)) {
+        Ok(value) => return Ok(value),
+        Err(err) => return Err(format!("Invalid Extended JSON: {}", err)),
+    }
+}
+
+/// Completes "2024-05-01" and "2024-05-01T10:00:00" into RFC 3339 dates in UTC.
+fn complete_date(date: &str) -> String {
+    match date.split_once('T') {
+        None if date.len() == 10 => return format!("{}T00:00:00Z", date),
+        Some((_day, time)) if !time.ends_with('Z') && !time.contains(['+', '-']) => {
+            return format!("{}Z", date)
+        }
+        _ => return date.to_string(),
+    }
+}
+
+/// Rewrites the parts of the input that come from the mongo shell rather than
+/// json as Extended JSON: `/pattern/flags` literals (where `\/` is a slash) and
+/// the helpers ISODate, ObjectId, NumberDecimal, NumberLong, NumberInt and BinData.
+/// Also returns, for each byte of the output, the byte of the input it comes from.
+fn expand_shell_syntax(string: &str) -> Result<(String, Vec<usize>), JsonError> {
+    let mut output = String::new();
+    let mut sources: Vec<usize> = Vec::new();
+    let mut chars = string.char_indices().peekable();
+    let mut in_string = false;
+    let mut escaped = false;
+
+    let mut emit = |output: &mut String, text: &str, source: usize| {
+        output.push_str(text);
+        sources.resize(output.len(), source);
+    };
+
+    while let Some((start, c)) = chars.next() {
+        if in_string {
+            emit(&mut output, c.encode_utf8(&mut [0; 4]), start);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        if c.is_ascii_alphabetic() {
+            let mut name = String::from(c);
+            while let Some((_i, next)) = chars.next_if(|(_i, c)| c.is_ascii_alphanumeric()) {
+                name.push(next);
+            }
+            if chars.next_if(|(_i, c)| *c == '(').is_none() {
+                for (i, c) in name.char_indices() {
+                    emit(&mut output, c.encode_utf8(&mut [0; 4]), start + i);
+                }
+                continue;
+            }
+            let helper = read_helper_arguments(&mut chars, &name)
+                .and_then(|arguments| shell_helper(&name, &arguments));
+            match helper {
+                Ok(value) => emit(&mut output, &value.to_string(), start),
+                Err(err) => return Err(JsonError::new(start, err)),
+            }
+            continue;
+        }
+
+        if c != '/' {
+            in_string = c == '"';
+            emit(&mut output, c.encode_utf8(&mut [0; 4]), start);
+            continue;
+        }
+
+        let mut pattern = String::new();
+        let mut closed = false;
+        while let Some((_i, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_i, '/')) => pattern.push('/'),
+                    Some((_i, next)) => {
+                        pattern.push(c);
+                        pattern.push(next);
+                    }
+                    None => break,
+                },
+                '/' => {
+                    closed = true;
+                    break;
+                }
+                _ => pattern.push(c),
+            }
         }
-        Err(_e) => return Err("Error parsing string".to_string()),
+        if !closed {
+            let message = "Unterminated regular expression".to_string();
+            return Err(JsonError::new(start, message));
+        }
+
+        let mut options = String::new();
+        while let Some((_i, option)) = chars.next_if(|(_i, c)| c.is_ascii_alphabetic()) {
+            options.push(option);
+        }
+        let regex = json!({"$regularExpression": {"pattern": pattern, "options": options}});
+        emit(&mut output, &regex.to_string(), start);
+    }
+
+    return Ok((output, sources));
+}
+
+/// Reads the json arguments of a helper up to its closing parenthesis.
+fn read_helper_arguments(
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+    name: &str,
+) -> Result<Vec<Value>, String> {
+    let mut arguments = String::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut closed = false;
+
+    for (_i, c) in chars.by_ref() {
+        if !in_string && c == ')' {
+            closed = true;
+            break;
+        }
+        arguments.push(c);
+        if escaped {
+            escaped = false;
+        } else if in_string && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            in_string = !in_string;
+        }
+    }
+    if !closed {
+        return Err(format!("Unterminated {}(", name));
+    }
+
+    match serde_json::from_str::<Vec<Value>>(&format!("[{}]", arguments)) {
+        Ok(arguments) => return Ok(arguments),
+        Err(_e) => return Err(format!("Invalid arguments for {}()", name)),
+    }
+}
+
+fn shell_helper(name: &str, arguments: &[Value]) -> Result<Value, String> {
+    let number_text = |value: &Value| match value {
+        Value::String(text) => Some(text.to_owned()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    };
+
+    match (name, arguments) {
+        ("ISODate", []) => {
+            let millis = DateTime::now().timestamp_millis().to_string();
+            return Ok(json!({"$date": {"$numberLong": millis}}));
+        }
+        ("ISODate", [Value::String(date)]) => return Ok(json!({ "$date": date })),
+        ("ObjectId", []) => return Ok(json!({"$oid": ObjectId::new().to_hex()})),
+        ("ObjectId", [Value::String(id)]) => return Ok(json!({ "$oid": id })),
+        ("NumberDecimal" | "NumberLong" | "NumberInt", [value]) => {
+            let key = match name {
+                "NumberDecimal" => "$numberDecimal",
+                "NumberLong" => "$numberLong",
+                _ => "$numberInt",
+            };
+            match number_text(value) {
+                Some(text) => return Ok(json!({ key: text })),
+                None => return Err(format!("{}() needs a number", name)),
+            }
+        }
+        ("BinData", [Value::Number(subtype), Value::String(base64)]) => {
+            let subtype = match subtype.as_u64() {
+                Some(subtype) if subtype <= 0xff => format!("{:02x}", subtype),
+                _ => return Err("The subtype of BinData() must be a byte".to_string()),
+            };
+            return Ok(json!({"$binary": {"base64": base64, "subType": subtype}}));
+        }
+        ("ISODate" | "ObjectId" | "BinData", _) => {
+            return Err(format!("Invalid arguments for {}()", name))
+        }
+        _ => return Err(format!("Unknown function {}()", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, oid::ObjectId, spec::BinarySubtype, Bson, DateTime, Decimal128};
+
+    use super::{string_to_bson, string_to_document};
+
+    fn read(string: &str) -> Bson {
+        match string_to_bson(string) {
+            Ok(value) => return value,
+            Err(err) => panic!("{} at {}", err.get_message(), err.get_offset()),
+        }
+    }
+
+    fn error_offset(string: &str) -> usize {
+        match string_to_bson(string) {
+            Ok(value) => panic!("{} was read as {}", string, value),
+            Err(err) => return err.get_offset(),
+        }
+    }
+
+    #[test]
+    fn reads_extended_json() {
+        let id = "65f0c0ffee0123456789abcd";
+        let oid = ObjectId::parse_str(id).unwrap();
+        assert_eq!(
+            read(&format!("{{\"$oid\": \"{}\"}}", id)),
+            Bson::ObjectId(oid)
+        );
+        assert_eq!(read("{\"$numberLong\": \"5\"}"), Bson::Int64(5));
+        assert_eq!(read("{\"$numberInt\": \"5\"}"), Bson::Int32(5));
+        assert_eq!(read("{\"$numberDouble\": \"5\"}"), Bson::Double(5.0));
+        let decimal: Decimal128 = "1.10".parse().unwrap();
+        assert_eq!(
+            read("{\"$numberDecimal\": \"1.10\"}"),
+            Bson::Decimal128(decimal)
+        );
+        let binary = read("{\"$binary\": {\"base64\": \"AQI=\", \"subType\": \"00\"}}");
+        match binary {
+            Bson::Binary(binary) => {
+                assert_eq!(binary.subtype, BinarySubtype::Generic);
+                assert_eq!(binary.bytes, vec![1, 2]);
+            }
+            other => panic!("{} isnt binary", other),
+        }
+        assert_eq!(
+            read("{\"a\": [{\"$minKey\": 1}, {\"b\": {\"$maxKey\": 1}}]}"),
+            Bson::Document(doc! { "a": [Bson::MinKey, { "b": Bson::MaxKey }] })
+        );
+    }
+
+    #[test]
+    fn reads_dates() {
+        let date = DateTime::parse_rfc3339_str("2024-05-01T00:00:00Z").unwrap();
+        assert_eq!(read("{\"$date\": \"2024-05-01\"}"), Bson::DateTime(date));
+        assert_eq!(
+            read("{\"$date\": \"2024-05-01T00:00:00\"}"),
+            Bson::DateTime(date)
+        );
+        let millis = date.timestamp_millis();
+        assert_eq!(
+            read(&format!("{{\"$date\": {}}}", millis)),
+            Bson::DateTime(date)
+        );
+        assert_eq!(
+            read("ISODate(\"2024-05-01T02:00:00+02:00\")"),
+            Bson::DateTime(date)
+        );
+        assert!(string_to_bson("{\"$date\": \"May 1st\"}").is_err());
+        assert!(matches!(read("ISODate()"), Bson::DateTime(_)));
+    }
+
+    #[test]
+    fn reads_shell_helpers() {
+        let id = "65f0c0ffee0123456789abcd";
+        let oid = ObjectId::parse_str(id).unwrap();
+        assert_eq!(read(&format!("ObjectId(\"{}\")", id)), Bson::ObjectId(oid));
+        assert!(matches!(read("ObjectId()"), Bson::ObjectId(_)));
+        assert_eq!(read("NumberLong(7)"), Bson::Int64(7));
+        assert_eq!(read("NumberInt(\"7\")"), Bson::Int32(7));
+        let decimal: Decimal128 = "0.1".parse().unwrap();
+        assert_eq!(read("NumberDecimal(\"0.1\")"), Bson::Decimal128(decimal));
+        assert!(matches!(read("BinData(4, \"AQI=\")"), Bson::Binary(_)));
+        assert_eq!(
+            string_to_document("{\"name\": \"ObjectId(x)\", \"n\": NumberLong( 2 )}")
+                .ok()
+                .unwrap(),
+            doc! { "name": "ObjectId(x)", "n": 2_i64 }
+        );
+    }
+
+    #[test]
+    fn reads_regular_expression_literals() {
+        let expected = Bson::RegularExpression(bson::Regex {
+            pattern: "^a/b".to_string(),
+            options: "i".to_string(),
+        });
+        assert_eq!(read("/^a\\/b/i"), expected);
+        assert_eq!(
+            read("{\"path\": \"/not/a/regex\"}"),
+            Bson::Document(doc! { "path": "/not/a/regex" })
+        );
+    }
+
+    #[test]
+    fn locates_errors_in_the_input() {
+        assert_eq!(error_offset("{\"a\": /abc"), 6);
+        assert_eq!(error_offset("{\"a\": Foo(1)}"), 6);
+        assert_eq!(error_offset("{\"a\": BinData(300, \"\")}"), 6);
+        assert_eq!(error_offset("{\"a\": NumberLong(1}"), 6);
+        // The offset is in the input, where the helper is still unexpanded
+        assert_eq!(error_offset("{\"a\": ObjectId(), \"b\": tru}"), 26);
+        assert!(string_to_document("[1]").is_err());
     }
 }
@@ -1,13 +1,26 @@
 use std::fmt;
 
-use bson::Document;
+use bson::{oid::ObjectId, Bson, Document};
 use serde::Deserialize;
 
+use crate::{
+    filter::{get_path_values, Filter},
+    index::{index_name, Index},
+    ordering::{sort_documents, values_equal},
+    planner::{plan_query, QueryPlan},
+    text::{is_text_index, TextIndex, TextSearch},
+    update::apply_update,
+};
+
 #[derive(serde::Serialize, Deserialize, Clone)]
 pub struct Collection {
     name: String,
     num_documents: usize,
     documents: Vec<Document>,
+    #[serde(default)]
+    indexes: Vec<Index>,
+    #[serde(default)]
+    text_index: Option<TextIndex>,
 }
 
 pub enum CollectionResult {
@@ -15,6 +28,13 @@ pub enum CollectionResult {
     CollectionDoesntExist,
 }
 
+/// Why a change to the documents of a collection was rejected.
+pub enum WriteError {
+    DuplicateId(Bson),
+    DuplicateKey(String),
+    Failed(String),
+}
+
 impl fmt::Display for Collection {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "Collection Name: {}", self.name)?;
@@ -33,6 +53,8 @@ impl Collection {
             name,
             num_documents: 0,
             documents: Vec::new(),
+            indexes: Vec::new(),
+            text_index: None,
         }
     }
 
@@ -44,65 +66,561 @@ impl Collection {
         return self.num_documents;
     }
 
-    pub fn add_to_collection(&mut self, doc: Document) {
+    /// Inserts the document, generating an ObjectId `_id` when it has none.
+    /// Returns the `_id` of the document, or why it cant be inserted.
+    pub fn add_to_collection(&mut self, doc: Document) -> Result<Bson, WriteError> {
+        let doc = match doc.get("_id") {
+            Some(id) => {
+                if self.contains_id(id) {
+                    return Err(WriteError::DuplicateId(id.clone()));
+                }
+                doc
+            }
+            None => {
+                let mut with_id = Document::new();
+                with_id.insert("_id", ObjectId::new());
+                with_id.extend(doc);
+                with_id
+            }
+        };
+
+        self.check_unique(&doc, None)?;
+
+        let id = doc.get("_id").cloned().unwrap_or(Bson::Null);
+        for index in self.indexes.iter_mut() {
+            index.add_document(&doc, self.documents.len());
+        }
+        if let Some(text_index) = &mut self.text_index {
+            text_index.add_document(&doc, self.documents.len());
+        }
         self.documents.push(doc);
         self.num_documents += 1;
+        return Ok(id);
+    }
+
+    fn contains_id(&self, id: &Bson) -> bool {
+        return self
+            .documents
+            .iter()
+            .any(|doc| matches!(doc.get("_id"), Some(other) if values_equal(other, id)));
     }
-    #[allow(dead_code)]
+
+    /// Checks that the document doesnt repeat a key of a unique index. The
+    /// document at `position`, if any, is the one it replaces.
+    fn check_unique(&self, doc: &Document, position: Option<usize>) -> Result<(), WriteError> {
+        for index in self.indexes.iter() {
+            if let Some(key) = index.conflicting_key(doc, position) {
+                return Err(WriteError::DuplicateKey(key));
+            }
+        }
+
+        return Ok(());
+    }
+
     pub fn get_collection(&self) -> &Vec<Document> {
         return &self.documents;
     }
 
-    pub fn simple_search(&self, query: Document) -> Vec<Document> {
-        let mut sorted: Vec<Document> = Vec::new();
+    /// Chooses how the documents matching the query are found.
+    pub fn plan(&self, query: &Document) -> QueryPlan {
+        return plan_query(
+            &self.indexes,
+            self.text_index.as_ref(),
+            &self.documents,
+            query,
+        );
+    }
+
+    /// Returns the names of the indexes, the text index last.
+    pub fn get_index_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.indexes.iter().map(|index| index.get_name()).collect();
+        if let Some(text_index) = &self.text_index {
+            names.push(text_index.get_name());
+        }
+
+        return names;
+    }
+
+    /// Returns the size of the documents once encoded as BSON, in bytes.
+    pub fn data_size(&self) -> usize {
+        let mut size = 0;
         for doc in self.documents.iter() {
-            let mut has_keys = true;
-            for (key, value) in query.iter() {
-                if !doc.contains_key(key) {
-                    has_keys = false;
-                    break;
-                }
-                if doc.get(key).ne(&Some(value)) {
-                    has_keys = false;
-                    break;
-                }
+            let mut bytes: Vec<u8> = Vec::new();
+            if doc.to_writer(&mut bytes).is_ok() {
+                size += bytes.len();
             }
-            if has_keys {
-                sorted.push(doc.clone());
+        }
+
+        return size;
+    }
+
+    pub fn has_text_index(&self) -> bool {
+        return self.text_index.is_some();
+    }
+
+    /// Returns the relevance of the document for the `$text` search of the query.
+    pub fn text_score(&self, doc: &Document, query: &Document) -> f64 {
+        match (&self.text_index, query.get("$text")) {
+            (Some(text_index), Some(search)) => {
+                return text_index.score(doc, &TextSearch::parse(search))
             }
+            _ => return 0.0,
+        }
+    }
+
+    /// Returns the positions of the documents matching the query and how many
+    /// documents the plan had to examine to find them.
+    pub fn execute_plan(&self, plan: &QueryPlan, query: &Filter) -> (Vec<usize>, usize) {
+        let candidates = plan.candidates(self.documents.len());
+        let examined = candidates.len();
+        let positions = candidates
+            .into_iter()
+            .filter(|position| query.matches(&self.documents[*position]))
+            .collect();
+
+        return (positions, examined);
+    }
+
+    fn matching_positions(&self, query: &Filter) -> Vec<usize> {
+        let plan = self.plan(query.get_document());
+        let (positions, _examined) = self.execute_plan(&plan, query);
+
+        return positions;
+    }
+
+    pub fn simple_search(&self, query: &Filter) -> Vec<Document> {
+        let mut sorted: Vec<Document> = Vec::new();
+        for position in self.matching_positions(query) {
+            sorted.push(self.documents[position].clone());
         }
 
         return sorted;
     }
 
-    pub fn simple_delete(&mut self, query: Document) {
-        self.documents.retain(|doc| {
-            let mut has_keys = true;
-            for (key, value) in query.iter() {
-                if !doc.contains_key(key) || doc.get(key) != Some(value) {
-                    has_keys = false;
-                    break;
+    pub fn simple_delete(&mut self, query: &Filter) -> usize {
+        let mut positions = self.matching_positions(query);
+        positions.sort_unstable();
+        let mut position = 0;
+        self.documents.retain(|_doc| {
+            let keep = positions.binary_search(&position).is_err();
+            position += 1;
+            keep
+        });
+        self.num_documents = self.documents.len();
+        if !positions.is_empty() {
+            self.rebuild_indexes();
+        }
+
+        return positions.len();
+    }
+
+    pub fn count_documents(&self, query: &Filter) -> usize {
+        return self.matching_positions(query).len();
+    }
+
+    /// Returns the different values of the field among the documents matching
+    /// the query, in the order they are found. Arrays count as each of their elements.
+    pub fn distinct_values(&self, field: &str, query: &Filter) -> Vec<Bson> {
+        let mut values: Vec<Bson> = Vec::new();
+        for position in self.matching_positions(query) {
+            for value in get_path_values(&self.documents[position], field) {
+                let items = match value {
+                    Bson::Array(items) => items.iter().collect(),
+                    _ => vec![value],
+                };
+                for item in items {
+                    if !values.iter().any(|value| values_equal(value, item)) {
+                        values.push(item.clone());
+                    }
                 }
             }
-            !has_keys
-        });
+        }
+
+        return values;
     }
 
-    pub fn get_sorted_collection(&self, query: Document) -> Vec<Document> {
-        let mut sorted: Vec<Document> = Vec::new();
-        for doc in self.documents.iter() {
-            let mut has_keys = true;
-            for key in query.keys() {
-                if !doc.contains_key(key) {
-                    has_keys = false;
-                    break;
+    /// Applies the update to the documents matching the query, only to the first
+    /// one unless `multi` is set. Nothing is modified if any update fails.
+    pub fn update_documents(
+        &mut self,
+        query: &Filter,
+        update: Document,
+        multi: bool,
+    ) -> Result<usize, WriteError> {
+        let mut updated: Vec<(usize, Document)> = Vec::new();
+        for i in self.matching_positions(query) {
+            let doc = &self.documents[i];
+            let mut new_doc = doc.clone();
+            apply_update(&mut new_doc, &update).map_err(WriteError::Failed)?;
+            if !same_id(new_doc.get("_id"), doc.get("_id")) {
+                return Err(WriteError::Failed(
+                    "Cant modify the field '_id'".to_string(),
+                ));
+            }
+            // An equal `_id` of another type, such as 1.0 for 1, leaves it as it was
+            if let Some(id) = doc.get("_id") {
+                new_doc.insert("_id", id.clone());
+            }
+            updated.push((i, new_doc));
+            if !multi {
+                break;
+            }
+        }
+
+        // The updated documents can conflict among themselves, so they are
+        // checked one by one and the earlier ones restored on a conflict
+        let count = updated.len();
+        let mut previous: Vec<(usize, Document)> = Vec::new();
+        for (i, new_doc) in updated {
+            if let Err(err) = self.check_unique(&new_doc, Some(i)) {
+                for (i, old_doc) in previous.into_iter().rev() {
+                    self.set_document(i, old_doc);
                 }
+                return Err(err);
+            }
+            previous.push((i, self.documents[i].clone()));
+            self.set_document(i, new_doc);
+        }
+
+        return Ok(count);
+    }
+
+    /// Swaps the first document matching the query with the replacement, which
+    /// keeps the `_id` of the document it replaces.
+    pub fn replace_document(
+        &mut self,
+        query: &Filter,
+        replacement: Document,
+    ) -> Result<usize, WriteError> {
+        let position = match self.matching_positions(query).first() {
+            Some(position) => *position,
+            None => return Ok(0),
+        };
+
+        let doc = &self.documents[position];
+        let mut new_doc = Document::new();
+        match (doc.get("_id"), replacement.get("_id")) {
+            (Some(old_id), Some(new_id)) if !values_equal(old_id, new_id) => {
+                return Err(WriteError::Failed(
+                    "Cant modify the field '_id'".to_string(),
+                ));
+            }
+            (Some(old_id), None) => {
+                new_doc.insert("_id", old_id.clone());
+            }
+            _ => {}
+        }
+        new_doc.extend(replacement);
+        // An equal `_id` of another type, such as 1.0 for 1, is left as it was
+        if let Some(id) = doc.get("_id") {
+            new_doc.insert("_id", id.clone());
+        }
+        self.check_unique(&new_doc, Some(position))?;
+        self.set_document(position, new_doc);
+
+        return Ok(1);
+    }
+
+    fn set_document(&mut self, position: usize, doc: Document) {
+        for index in self.indexes.iter_mut() {
+            index.remove_document(&self.documents[position], position);
+            index.add_document(&doc, position);
+        }
+        if let Some(text_index) = &mut self.text_index {
+            text_index.remove_document(&self.documents[position], position);
+            text_index.add_document(&doc, position);
+        }
+        self.documents[position] = doc;
+    }
+
+    /// Creates an index over the fields of the specification and returns its name.
+    /// A unique index is refused when the documents already repeat a key.
+    pub fn create_index(&mut self, keys: Document, options: &Document) -> Result<String, String> {
+        let name = index_name(&keys);
+        if self.indexes.iter().any(|index| index.get_name() == name) {
+            return Err(format!("The index '{}' already exists", name));
+        }
+
+        if is_text_index(&keys) {
+            if let Some(text_index) = &self.text_index {
+                return Err(format!(
+                    "The collection already has the text index '{}'",
+                    text_index.get_name()
+                ));
             }
-            if has_keys {
-                sorted.push(doc.clone());
+            if !options.is_empty() {
+                return Err("Text indexes dont take options".to_string());
             }
+            let mut text_index = TextIndex::new(name.to_owned(), keys);
+            text_index.rebuild(&self.documents);
+            self.text_index = Some(text_index);
+            return Ok(name);
         }
 
+        let mut index = Index::new(name.to_owned(), keys, options);
+        index.rebuild(&self.documents);
+        if let Some(key) = index.duplicate_key() {
+            return Err(format!("Duplicate key {}", key));
+        }
+        self.indexes.push(index);
+
+        return Ok(name);
+    }
+
+    /// Drops the index with that name. Returns false when there is none.
+    pub fn drop_index(&mut self, name: &str) -> bool {
+        if matches!(&self.text_index, Some(text_index) if text_index.get_name() == name) {
+            self.text_index = None;
+            return true;
+        }
+
+        let before = self.indexes.len();
+        self.indexes.retain(|index| index.get_name() != name);
+
+        return self.indexes.len() != before;
+    }
+
+    pub fn rebuild_indexes(&mut self) {
+        for index in self.indexes.iter_mut() {
+            index.rebuild(&self.documents);
+        }
+        if let Some(text_index) = &mut self.text_index {
+            text_index.rebuild(&self.documents);
+        }
+    }
+
+    /// Returns the paths of the fields found in the first `sample` documents,
+    /// including the fields of embedded documents such as "address.city".
+    pub fn sample_fields(&self, sample: usize) -> Vec<String> {
+        let mut fields: Vec<String> = Vec::new();
+        for doc in self.documents.iter().take(sample) {
+            add_field_paths(doc, "", &mut fields);
+        }
+
+        return fields;
+    }
+
+    /// Searches the documents matching the query, ordered by the sort specification.
+    pub fn get_sorted_collection(&self, query: &Filter, sort: &Document) -> Vec<Document> {
+        let mut sorted = self.simple_search(query);
+        sort_documents(&mut sorted, sort);
+
         return sorted;
     }
 }
+
+fn add_field_paths(doc: &Document, prefix: &str, fields: &mut Vec<String>) {
+    for (key, value) in doc.iter() {
+        let path = format!("{}{}", prefix, key);
+        if let Bson::Document(embedded) = value {
+            add_field_paths(embedded, &format!("{}.", path), fields);
+        }
+        if !fields.contains(&path) {
+            fields.push(path);
+        }
+    }
+}
+
+/// Compares two `_id`s like filters and unique indexes do, where 1 and 1.0 are
+/// the same value.
+fn same_id(a: Option<&Bson>, b: Option<&Bson>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => return values_equal(a, b),
+        (None, None) => return true,
+        _ => return false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, Bson, Document};
+
+    use super::{Collection, WriteError};
+    use crate::filter::Filter;
+
+    fn collection_with(documents: Vec<Document>) -> Collection {
+        let mut collection = Collection::new("test".to_string());
+        for doc in documents {
+            assert!(collection.add_to_collection(doc).is_ok());
+        }
+        return collection;
+    }
+
+    fn filter(document: Document) -> Filter {
+        return Filter::new(document).unwrap();
+    }
+
+    #[test]
+    fn updates_keep_the_id() {
+        let mut collection = collection_with(vec![doc! { "_id": 1, "a": 1 }]);
+        let same_id = doc! { "$set": { "_id": 1.0, "a": 2 } };
+        let result = collection.update_documents(&filter(doc! {}), same_id, true);
+        assert!(matches!(result, Ok(1)));
+
+        let new_id = doc! { "$set": { "_id": 2 } };
+        let result = collection.update_documents(&filter(doc! {}), new_id, true);
+        assert!(matches!(result, Err(WriteError::Failed(_))));
+        let result =
+            collection.update_documents(&filter(doc! {}), doc! { "$unset": { "_id": "" } }, true);
+        assert!(matches!(result, Err(WriteError::Failed(_))));
+        assert_eq!(
+            collection.get_collection(),
+            &vec![doc! { "_id": 1, "a": 2 }]
+        );
+    }
+
+    #[test]
+    fn updates_one_or_every_document() {
+        let documents = vec![doc! { "_id": 1, "a": 1 }, doc! { "_id": 2, "a": 1 }];
+        let mut collection = collection_with(documents);
+        let update = doc! { "$inc": { "a": 1 } };
+        let result = collection.update_documents(&filter(doc! { "a": 1 }), update.clone(), false);
+        assert!(matches!(result, Ok(1)));
+        let result = collection.update_documents(&filter(doc! {}), update, true);
+        assert!(matches!(result, Ok(2)));
+        assert_eq!(collection.count_documents(&filter(doc! { "a": 3 })), 1);
+    }
+
+    #[test]
+    fn replaces_the_first_match() {
+        let documents = vec![doc! { "_id": 1, "a": 1 }, doc! { "_id": 2, "a": 1 }];
+        let mut collection = collection_with(documents);
+        let result = collection.replace_document(&filter(doc! { "a": 1 }), doc! { "b": 2 });
+        assert!(matches!(result, Ok(1)));
+        let result = collection.replace_document(&filter(doc! { "_id": 2 }), doc! { "_id": 2.0 });
+        assert!(matches!(result, Ok(1)));
+        let expected = vec![doc! { "_id": 1, "b": 2 }, doc! { "_id": 2 }];
+        assert_eq!(collection.get_collection(), &expected);
+
+        let result = collection.replace_document(&filter(doc! { "_id": 1 }), doc! { "_id": 3 });
+        assert!(matches!(result, Err(WriteError::Failed(_))));
+        let result = collection.replace_document(&filter(doc! { "a": 5 }), doc! { "c": 1 });
+        assert!(matches!(result, Ok(0)));
+    }
+
+    #[test]
+    fn generates_object_ids() {
+        let mut collection = Collection::new("test".to_string());
+        let first = collection.add_to_collection(doc! { "a": 1 });
+        let second = collection.add_to_collection(doc! { "a": 1 });
+        match (first, second) {
+            (Ok(Bson::ObjectId(first)), Ok(Bson::ObjectId(second))) => assert_ne!(first, second),
+            _ => panic!("Expected two ObjectIds"),
+        }
+        let doc = &collection.get_collection()[0];
+        assert_eq!(doc.keys().next().map(|key| key.as_str()), Some("_id"));
+        assert_eq!(collection.get_num_docuents(), 2);
+    }
+
+    #[test]
+    fn rejects_duplicate_ids() {
+        let mut collection = collection_with(vec![doc! { "_id": 1 }]);
+        let result = collection.add_to_collection(doc! { "_id": 1.0 });
+        assert!(matches!(result, Err(WriteError::DuplicateId(_))));
+        let result = collection.add_to_collection(doc! { "_id": "1" });
+        assert!(matches!(result, Ok(Bson::String(_))));
+        assert_eq!(collection.get_num_docuents(), 2);
+    }
+
+    #[test]
+    fn counts_and_deletes_documents() {
+        let documents = vec![
+            doc! { "a": 1 },
+            doc! { "a": 2 },
+            doc! { "a": 2 },
+            doc! { "b": 1 },
+        ];
+        let mut collection = collection_with(documents);
+        assert_eq!(collection.count_documents(&filter(doc! {})), 4);
+        assert_eq!(collection.count_documents(&filter(doc! { "a": 2 })), 2);
+        assert_eq!(collection.simple_delete(&filter(doc! { "a": 2 })), 2);
+        assert_eq!(collection.get_num_docuents(), 2);
+        assert_eq!(collection.count_documents(&filter(doc! {})), 2);
+    }
+
+    #[test]
+    fn finds_distinct_values() {
+        let documents = vec![
+            doc! { "tags": ["a", "b"], "n": 1 },
+            doc! { "tags": "b", "n": 1.0 },
+            doc! { "tags": ["c"], "n": 2 },
+            doc! { "n": 3 },
+        ];
+        let collection = collection_with(documents);
+        let tags = collection.distinct_values("tags", &filter(doc! {}));
+        assert_eq!(
+            tags,
+            vec![Bson::from("a"), Bson::from("b"), Bson::from("c")]
+        );
+        let numbers = collection.distinct_values("n", &filter(doc! { "n": { "$lt": 3 } }));
+        assert_eq!(numbers, vec![Bson::Int32(1), Bson::Int32(2)]);
+    }
+
+    #[test]
+    fn unique_indexes_refuse_repeated_keys() {
+        let mut collection = collection_with(vec![doc! { "_id": 1, "email": "a" }]);
+        let unique = doc! { "unique": true };
+        assert!(collection
+            .create_index(doc! { "email": 1 }, &unique)
+            .is_ok());
+
+        let result = collection.add_to_collection(doc! { "_id": 2, "email": "a" });
+        assert!(matches!(result, Err(WriteError::DuplicateKey(_))));
+        let result = collection.add_to_collection(doc! { "_id": 3, "email": ["b", "a"] });
+        assert!(matches!(result, Err(WriteError::DuplicateKey(_))));
+        assert!(collection
+            .add_to_collection(doc! { "_id": 4, "email": "b" })
+            .is_ok());
+
+        // Updating a document to its own key isnt a conflict
+        let same = doc! { "$set": { "email": "a" } };
+        let result = collection.update_documents(&filter(doc! { "_id": 1 }), same, false);
+        assert!(matches!(result, Ok(1)));
+
+        // Both documents would end up with the same key, so neither changes
+        let all = doc! { "$set": { "email": "c" } };
+        let result = collection.update_documents(&filter(doc! {}), all, true);
+        assert!(matches!(result, Err(WriteError::DuplicateKey(_))));
+        assert_eq!(
+            collection.simple_search(&filter(doc! { "email": "c" })),
+            Vec::<Document>::new()
+        );
+    }
+
+    #[test]
+    fn unique_indexes_need_distinct_documents() {
+        let documents = vec![doc! { "_id": 1, "a": 1 }, doc! { "_id": 2, "a": 1.0 }];
+        let mut collection = collection_with(documents);
+        let unique = doc! { "unique": true };
+        assert!(collection.create_index(doc! { "a": 1 }, &unique).is_err());
+        assert!(collection
+            .create_index(doc! { "a": 1 }, &Document::new())
+            .is_ok());
+        assert!(collection
+            .create_index(doc! { "a": 1 }, &Document::new())
+            .is_err());
+    }
+
+    #[test]
+    fn sparse_and_partial_indexes_skip_documents() {
+        let documents = vec![doc! { "_id": 1 }, doc! { "_id": 2, "code": null }];
+        let mut collection = collection_with(documents);
+        let sparse = doc! { "unique": true, "sparse": true };
+        // The document with a null code is in the index, the one without it isnt
+        assert!(collection.create_index(doc! { "code": 1 }, &sparse).is_ok());
+        assert!(collection.add_to_collection(doc! { "_id": 3 }).is_ok());
+        let result = collection.add_to_collection(doc! { "_id": 4, "code": null });
+        assert!(matches!(result, Err(WriteError::DuplicateKey(_))));
+
+        let mut collection = collection_with(vec![doc! { "_id": 1, "n": 1, "active": false }]);
+        let partial = doc! { "unique": true, "partialFilterExpression": { "active": true } };
+        assert!(collection.create_index(doc! { "n": 1 }, &partial).is_ok());
+        let inactive = doc! { "_id": 2, "n": 1, "active": false };
+        assert!(collection.add_to_collection(inactive).is_ok());
+        assert!(collection
+            .add_to_collection(doc! { "_id": 3, "n": 1, "active": true })
+            .is_ok());
+        let result = collection.add_to_collection(doc! { "_id": 4, "n": 1, "active": true });
+        assert!(matches!(result, Err(WriteError::DuplicateKey(_))));
+    }
+}
@@ -0,0 +1,509 @@
+use std::fs;
+
+use crate::{
+    commit_changes, db_open,
+    lexer::{lex_statement, statement_is_complete},
+    output::{OutputMode, OUTPUT_MODES},
+    report_unterminated, run_input, save_database,
+    settings::Settings,
+    Database,
+};
+
+/// What the REPL does after a dot command.
+pub enum CommandResult {
+    CommandSuccess,
+    CommandExit,
+    CommandUsage,
+    CommandFailed(String),
+}
+
+type CommandHandler = fn(&[String], &mut Database, &mut Settings) -> CommandResult;
+
+/// A command of the REPL, written with a leading dot.
+pub struct DotCommand {
+    name: &'static str,
+    arguments: &'static str,
+    description: &'static str,
+    handler: CommandHandler,
+}
+
+impl DotCommand {
+    pub fn get_name(&self) -> &'static str {
+        return self.name;
+    }
+
+    fn usage(&self) -> String {
+        return format!("{} {}", self.name, self.arguments)
+            .trim_end()
+            .to_string();
+    }
+}
+
+pub const DOT_COMMANDS: [DotCommand; 9] = [
+    DotCommand {
+        name: ".collections",
+        arguments: "",
+        description: "Lists the collections of the database",
+        handler: list_collections,
+    },
+    DotCommand {
+        name: ".exit",
+        arguments: "",
+        description: "Saves the database and exits",
+        handler: exit,
+    },
+    DotCommand {
+        name: ".help",
+        arguments: "[topic]",
+        description: "Lists the commands, or shows how to use a command or statement",
+        handler: show_help,
+    },
+    DotCommand {
+        name: ".mode",
+        arguments: "[shell|relaxed|canonical|pretty|compact]",
+        description: "Sets how documents are printed, or shows the current mode",
+        handler: set_mode,
+    },
+    DotCommand {
+        name: ".open",
+        arguments: "<file>",
+        description: "Saves the database and opens another one, which is created if missing",
+        handler: open_database,
+    },
+    DotCommand {
+        name: ".read",
+        arguments: "<script>",
+        description: "Runs the statements and commands of a file",
+        handler: read_script,
+    },
+    DotCommand {
+        name: ".save",
+        arguments: "[file]",
+        description: "Saves the database to its file, or a copy of it to another file",
+        handler: save,
+    },
+    DotCommand {
+        name: ".stats",
+        arguments: "[collection]",
+        description: "Shows the documents, size and indexes of the collections",
+        handler: show_stats,
+    },
+    DotCommand {
+        name: ".timer",
+        arguments: "[on|off]",
+        description: "Prints how long each statement takes, or shows if it does",
+        handler: set_timer,
+    },
+];
+
+/// The keyword, usage and description of each statement, for `.help`.
+const STATEMENT_HELP: [(&str, &str, &str); 15] = [
+    (
+        "insert",
+        "insert <collection> <document>",
+        "Inserts the document, with a new ObjectId _id unless it has one",
+    ),
+    (
+        "find",
+        "find <collection> [filter] [projection] [sort <json>] [skip <n>] [limit <n>]",
+        "Prints the documents matching the filter",
+    ),
+    (
+        "create",
+        "create <collection>",
+        "Creates an empty collection",
+    ),
+    ("peek", "peek", "Prints the names of the collections"),
+    ("commit", "commit", "Saves the database to its file"),
+    (
+        "delete",
+        "delete <collection> <filter>",
+        "Deletes the documents matching the filter",
+    ),
+    (
+        "update",
+        "update <collection> <filter> <update> [upsert]",
+        "Applies the update operators to every document matching the filter",
+    ),
+    (
+        "updateOne",
+        "updateOne <collection> <filter> <update> [upsert]",
+        "Applies the update operators to the first document matching the filter",
+    ),
+    (
+        "replace",
+        "replace <collection> <filter> <document> [upsert]",
+        "Swaps the first document matching the filter with the new one",
+    ),
+    (
+        "count",
+        "count <collection> [filter]",
+        "Prints how many documents match the filter",
+    ),
+    (
+        "distinct",
+        "distinct <collection> <field> [filter]",
+        "Prints the different values of the field among the documents matching the filter",
+    ),
+    (
+        "aggregate",
+        "aggregate <collection> <stage>...",
+        "Runs the documents through a pipeline of stages",
+    ),
+    (
+        "createIndex",
+        "createIndex <collection> <keys> [options]",
+        "Creates an index, such as {\"age\": 1} or {\"title\": \"text\"}, with its options",
+    ),
+    (
+        "dropIndex",
+        "dropIndex <collection> <name|keys>",
+        "Drops the index with that name or specification",
+    ),
+    (
+        "explain",
+        "explain find <collection> [filter]",
+        "Prints how the find reads the collection instead of its documents",
+    ),
+];
+
+/// Runs a dot command. Returns true when the REPL has to exit.
+pub fn handle_command(input: &str, database: &mut Database, settings: &mut Settings) -> bool {
+    let words: Vec<String> = match lex_statement(input) {
+        Ok(tokens) => tokens
+            .iter()
+            .map(|token| token.get_text().to_string())
+            .collect(),
+        Err(err) => {
+            eprintln!("{}", err.get_message());
+            return false;
+        }
+    };
+
+    let command = match DOT_COMMANDS.iter().find(|command| command.name == words[0]) {
+        Some(command) => command,
+        None => {
+            println!("Command '{}' not recognized. Use .help to list them", input);
+            return false;
+        }
+    };
+
+    match (command.handler)(&words[1..], database, settings) {
+        CommandResult::CommandSuccess => {}
+        CommandResult::CommandExit => return true,
+        CommandResult::CommandUsage => eprintln!("Usage: {}", command.usage()),
+        CommandResult::CommandFailed(err) => eprintln!("{}", err),
+    }
+    return false;
+}
+
+fn list_collections(
+    arguments: &[String],
+    database: &mut Database,
+    _settings: &mut Settings,
+) -> CommandResult {
+    if !arguments.is_empty() {
+        return CommandResult::CommandUsage;
+    }
+
+    let names = database.get_collection_names();
+    if names.is_empty() {
+        println!("No collections.");
+    }
+    for name in names {
+        println!("{}", name);
+    }
+    return CommandResult::CommandSuccess;
+}
+
+fn exit(arguments: &[String], _database: &mut Database, _settings: &mut Settings) -> CommandResult {
+    if !arguments.is_empty() {
+        return CommandResult::CommandUsage;
+    }
+
+    println!("Bye!");
+    return CommandResult::CommandExit;
+}
+
+fn show_help(
+    arguments: &[String],
+    _database: &mut Database,
+    _settings: &mut Settings,
+) -> CommandResult {
+    match arguments {
+        [] => {
+            println!("Commands:");
+            for command in DOT_COMMANDS.iter() {
+                println!("  {:<48} {}", command.usage(), command.description);
+            }
+            let keywords: Vec<&str> = STATEMENT_HELP.iter().map(|help| help.0).collect();
+            println!("Statements:");
+            println!("  {}", keywords.join(", "));
+            println!("Use .help <command or statement> to see how to use one.");
+            return CommandResult::CommandSuccess;
+        }
+        [topic] => {
+            let topic = topic.trim_start_matches('.');
+            for command in DOT_COMMANDS.iter() {
+                if command.name[1..] == *topic {
+                    println!("{}", command.usage());
+                    println!("  {}", command.description);
+                    return CommandResult::CommandSuccess;
+                }
+            }
+            for (keyword, usage, description) in STATEMENT_HELP.iter() {
+                if *keyword == topic {
+                    println!("{}", usage);
+                    println!("  {}", description);
+                    return CommandResult::CommandSuccess;
+                }
+            }
+            return CommandResult::CommandFailed(format!("No help for '{}'", topic));
+        }
+        _ => return CommandResult::CommandUsage,
+    }
+}
+
+fn set_mode(
+    arguments: &[String],
+    _database: &mut Database,
+    settings: &mut Settings,
+) -> CommandResult {
+    match arguments {
+        [] => println!("Output mode: {}", settings.get_output_mode().get_name()),
+        [name] => match OutputMode::from_name(name) {
+            Some(mode) => settings.set_output_mode(mode),
+            None => {
+                return CommandResult::CommandFailed(format!(
+                    "Unknown output mode '{}'. Use one of: {}",
+                    name,
+                    OUTPUT_MODES.join(", ")
+                ))
+            }
+        },
+        _ => return CommandResult::CommandUsage,
+    }
+    return CommandResult::CommandSuccess;
+}
+
+fn open_database(
+    arguments: &[String],
+    database: &mut Database,
+    _settings: &mut Settings,
+) -> CommandResult {
+    let filename = match arguments {
+        [filename] => filename,
+        _ => return CommandResult::CommandUsage,
+    };
+
+    // The current database stays open if the file cant be read
+    let opened = match db_open(filename) {
+        Ok(opened) => opened,
+        Err(err) => return CommandResult::CommandFailed(err),
+    };
+    match commit_changes(database) {
+        Ok(ok) => println!("{}", ok),
+        Err(err) => return CommandResult::CommandFailed(err),
+    }
+    *database = opened;
+    println!("Opened {}.", filename);
+    return CommandResult::CommandSuccess;
+}
+
+/// Runs each statement of the script, which can take several lines like in
+/// the REPL, and stops at `.exit`.
+fn read_script(
+    arguments: &[String],
+    database: &mut Database,
+    settings: &mut Settings,
+) -> CommandResult {
+    let filename = match arguments {
+        [filename] => filename,
+        _ => return CommandResult::CommandUsage,
+    };
+    let script = match fs::read_to_string(filename) {
+        Ok(script) => script,
+        Err(_err) => return CommandResult::CommandFailed(format!("Couldnt read '{}'", filename)),
+    };
+    let path = match fs::canonicalize(filename) {
+        Ok(path) => path,
+        Err(_err) => return CommandResult::CommandFailed(format!("Couldnt read '{}'", filename)),
+    };
+    if settings.is_reading_script(&path) {
+        return CommandResult::CommandFailed(format!("'{}' is already being read", filename));
+    }
+
+    settings.push_script(path);
+    let result = run_script(&script, database, settings);
+    settings.pop_script();
+    return result;
+}
+
+fn run_script(script: &str, database: &mut Database, settings: &mut Settings) -> CommandResult {
+    let mut input = String::new();
+    for line in script.lines() {
+        if input.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+        if !input.is_empty() {
+            input.push('\n');
+        }
+        input.push_str(line);
+        if !input.trim_start().starts_with('.') && !statement_is_complete(&input) {
+            continue;
+        }
+        if run_input(&std::mem::take(&mut input), database, settings) {
+            return CommandResult::CommandExit;
+        }
+    }
+    if !input.is_empty() {
+        report_unterminated(&input);
+    }
+
+    return CommandResult::CommandSuccess;
+}
+
+fn save(arguments: &[String], database: &mut Database, _settings: &mut Settings) -> CommandResult {
+    let result = match arguments {
+        [] => commit_changes(database),
+        [filename] => save_database(database, filename),
+        _ => return CommandResult::CommandUsage,
+    };
+
+    match result {
+        Ok(ok) => println!("{}", ok),
+        Err(err) => return CommandResult::CommandFailed(err),
+    }
+    return CommandResult::CommandSuccess;
+}
+
+fn show_stats(
+    arguments: &[String],
+    database: &mut Database,
+    _settings: &mut Settings,
+) -> CommandResult {
+    let names = match arguments {
+        [] => {
+            let names = database.get_collection_names();
+            println!("Database: {}", database.get_filename());
+            println!("Collections: {}", names.len());
+            names
+        }
+        [name] => vec![name.to_owned()],
+        _ => return CommandResult::CommandUsage,
+    };
+
+    for name in names {
+        let collection = match database.find_collection(&name) {
+            Some(collection) => collection,
+            None => {
+                return CommandResult::CommandFailed(format!(
+                    "The collection '{}' doesnt exist",
+                    name
+                ))
+            }
+        };
+        let indexes = collection.get_index_names();
+        let indexes = match indexes.is_empty() {
+            true => "none".to_string(),
+            false => indexes.join(", "),
+        };
+        println!(
+            "{}: {} documents, {} bytes, indexes: {}",
+            name,
+            collection.get_num_docuents(),
+            collection.data_size(),
+            indexes
+        );
+    }
+    return CommandResult::CommandSuccess;
+}
+
+fn set_timer(
+    arguments: &[String],
+    _database: &mut Database,
+    settings: &mut Settings,
+) -> CommandResult {
+    match arguments
+        .iter()
+        .map(|argument| argument.as_str())
+        .collect::<Vec<&str>>()[..]
+    {
+        [] => match settings.get_timer() {
+            true => println!("Timer: on"),
+            false => println!("Timer: off"),
+        },
+        ["on"] => settings.set_timer(true),
+        ["off"] => settings.set_timer(false),
+        _ => return CommandResult::CommandUsage,
+    }
+    return CommandResult::CommandSuccess;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::{handle_command, read_script, CommandResult, DOT_COMMANDS, STATEMENT_HELP};
+    use crate::{output::OutputMode, settings::Settings, statement::STATEMENT_KEYWORDS, Database};
+
+    fn run(input: &str, database: &mut Database, settings: &mut Settings) -> bool {
+        return handle_command(input, database, settings);
+    }
+
+    #[test]
+    fn documents_every_statement() {
+        let keywords: Vec<&str> = STATEMENT_HELP.iter().map(|help| help.0).collect();
+        assert_eq!(keywords, STATEMENT_KEYWORDS);
+        assert!(DOT_COMMANDS
+            .iter()
+            .all(|command| command.get_name().starts_with('.')));
+    }
+
+    #[test]
+    fn changes_the_settings() {
+        let mut database = Database::new("test".to_string(), Vec::new());
+        let mut settings = Settings::new();
+
+        assert!(!run(".mode pretty", &mut database, &mut settings));
+        assert!(settings.get_output_mode() == OutputMode::Pretty);
+        assert!(!run(".mode table", &mut database, &mut settings));
+        assert!(settings.get_output_mode() == OutputMode::Pretty);
+        assert!(!run(".timer on", &mut database, &mut settings));
+        assert!(settings.get_timer());
+        assert!(!run(".timer maybe", &mut database, &mut settings));
+        assert!(settings.get_timer());
+        assert!(!run(".unknown", &mut database, &mut settings));
+        assert!(run(".exit", &mut database, &mut settings));
+    }
+
+    #[test]
+    fn reads_scripts_once_at_a_time() {
+        let path = env::temp_dir().join(format!("doclite-script-{}.txt", process::id()));
+        let path_text = path.to_string_lossy().to_string();
+        let script = format!(
+            "create things\n\n.read {}\ninsert things {{\n  \"a\": 1\n}}\n.exit\ninsert things {{}}\n",
+            path_text
+        );
+        fs::write(&path, script).unwrap();
+
+        let mut database = Database::new("test".to_string(), Vec::new());
+        let mut settings = Settings::new();
+        let result = read_script(&[path_text], &mut database, &mut settings);
+        fs::remove_file(&path).unwrap();
+
+        // The inner `.read` is refused and `.exit` stops the script
+        assert!(matches!(result, CommandResult::CommandExit));
+        let things = database.find_collection("things").unwrap();
+        assert_eq!(things.get_num_docuents(), 1);
+        assert!(!settings.is_reading_script(&path));
+
+        let missing = vec![path.to_string_lossy().to_string()];
+        let result = read_script(&missing, &mut database, &mut settings);
+        assert!(matches!(result, CommandResult::CommandFailed(_)));
+        assert!(matches!(
+            read_script(&[], &mut database, &mut settings),
+            CommandResult::CommandUsage
+        ));
+    }
+}
@@ -9,7 +9,10 @@ pub struct Database {
 
 impl Database {
     pub fn new(filename: String, tables: Vec<Collection>) -> Self {
-        Self { filename, collections: tables }
+        Self {
+            filename,
+            collections: tables,
+        }
     }
 
     pub fn get_filename(&self) -> String {
@@ -20,6 +23,25 @@ impl Database {
         return self.collections.as_mut();
     }
 
+    pub fn get_collection_names(&self) -> Vec<String> {
+        return self
+            .collections
+            .iter()
+            .map(|item| item.get_name())
+            .collect();
+    }
+
+    pub fn find_collection(&self, name: &str) -> Option<&Collection> {
+        return self.collections.iter().find(|item| item.get_name() == name);
+    }
+
+    /// Rebuilds the entries of every index, which are not saved in the database file.
+    pub fn rebuild_indexes(&mut self) {
+        for collection in self.collections.iter_mut() {
+            collection.rebuild_indexes();
+        }
+    }
+
     pub fn add_collection(&mut self, table: Collection) {
         self.collections.push(table);
     }
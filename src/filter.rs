@@ -0,0 +1,764 @@
+use std::cmp::Ordering;
+
+use bson::{Bson, Document};
+use regex::{Regex, RegexBuilder};
+
+use crate::{
+    ordering::{compare_bson, same_type_bracket, values_equal},
+    text::validate_text_search,
+};
+
+const COMPARISON_OPERATORS: [&str; 8] = ["$eq", "$gt", "$gte", "$lt", "$lte", "$ne", "$in", "$nin"];
+const ARRAY_OPERATORS: [&str; 3] = ["$all", "$elemMatch", "$size"];
+const LOGICAL_OPERATORS: [&str; 3] = ["$and", "$or", "$nor"];
+
+/// A validated filter with its regular expressions compiled, so a statement
+/// builds them once instead of for every document it matches.
+#[derive(Clone, Default)]
+pub struct Filter {
+    document: Document,
+    regexes: Vec<CompiledRegex>,
+}
+
+/// A regular expression of a filter with the pattern and options it was built from.
+#[derive(Clone)]
+struct CompiledRegex {
+    pattern: String,
+    options: String,
+    regex: Regex,
+}
+
+impl Filter {
+    /// Checks the filter of a statement, which can also use `$text` at its top
+    /// level, and compiles its regular expressions.
+    pub fn new(document: Document) -> Result<Self, String> {
+        let mut rest = document.clone();
+        if let Some(search) = rest.remove("$text") {
+            validate_text_search(&search)?;
+        }
+        let mut regexes: Vec<CompiledRegex> = Vec::new();
+        check_filter(&rest, &mut regexes)?;
+
+        return Ok(Self { document, regexes });
+    }
+
+    pub fn get_document(&self) -> &Document {
+        return &self.document;
+    }
+
+    /// Returns true when the document satisfies every key of the filter. A `$text`
+    /// condition is left to the text index, which only returns matching documents.
+    pub fn matches(&self, doc: &Document) -> bool {
+        return matches_document(doc, &self.document, &self.regexes);
+    }
+}
+
+/// Checks that every operator used in the filter is known and has a valid operand,
+/// so the matching functions can assume a well formed filter.
+pub fn validate_filter(filter: &Document) -> Result<(), String> {
+    return check_filter(filter, &mut Vec::new());
+}
+
+/// Validates the filter and compiles each regular expression it uses into `regexes`.
+fn check_filter(filter: &Document, regexes: &mut Vec<CompiledRegex>) -> Result<(), String> {
+    for (key, condition) in filter.iter() {
+        if key.starts_with('$') {
+            validate_logical(key, condition, regexes)?;
+            continue;
+        }
+        match condition {
+            Bson::Document(condition) if is_operator_document(condition) => {
+                validate_operators(condition, regexes)?;
+            }
+            Bson::RegularExpression(_) => {
+                compile_regex(condition, None, regexes)?;
+            }
+            _ => {}
+        }
+    }
+
+    return Ok(());
+}
+
+fn validate_logical(
+    operator: &str,
+    operand: &Bson,
+    regexes: &mut Vec<CompiledRegex>,
+) -> Result<(), String> {
+    if !LOGICAL_OPERATORS.contains(&operator) {
+        return Err(format!("Unknown top level operator '{}'", operator));
+    }
+
+    match operand {
+        Bson::Array(filters) if !filters.is_empty() => {
+            for filter in filters.iter() {
+                match filter {
+                    Bson::Document(filter) => check_filter(filter, regexes)?,
+                    _ => return Err(format!("'{}' needs an array of filters", operator)),
+                }
+            }
+            return Ok(());
+        }
+        _ => return Err(format!("'{}' needs a non empty array of filters", operator)),
+    }
+}
+
+fn validate_operators(
+    condition: &Document,
+    regexes: &mut Vec<CompiledRegex>,
+) -> Result<(), String> {
+    for (operator, operand) in condition.iter() {
+        if operator == "$not" {
+            match operand {
+                Bson::Document(inner) if is_operator_document(inner) => {
+                    validate_operators(inner, regexes)?;
+                    continue;
+                }
+                Bson::RegularExpression(_) => {
+                    compile_regex(operand, None, regexes)?;
+                    continue;
+                }
+                _ => return Err("'$not' needs an object of operators".to_string()),
+            }
+        }
+        if operator == "$regex" {
+            compile_regex(operand, condition.get("$options"), regexes)?;
+            continue;
+        }
+        if operator == "$options" {
+            if !condition.contains_key("$regex") {
+                return Err("'$options' needs '$regex'".to_string());
+            }
+            continue;
+        }
+        if ARRAY_OPERATORS.contains(&operator.as_str()) {
+            validate_array_operator(operator, operand, regexes)?;
+            continue;
+        }
+        if !COMPARISON_OPERATORS.contains(&operator.as_str()) {
+            return Err(format!("Unknown operator '{}'", operator));
+        }
+        match (operator.as_str(), operand) {
+            ("$in" | "$nin", Bson::Array(values)) => {
+                for value in values.iter() {
+                    if let Bson::RegularExpression(_) = value {
+                        compile_regex(value, None, regexes)?;
+                    }
+                }
+            }
+            ("$in" | "$nin", _) => return Err(format!("'{}' needs an array", operator)),
+            _ => {}
+        }
+    }
+
+    return Ok(());
+}
+
+fn validate_array_operator(
+    operator: &str,
+    operand: &Bson,
+    regexes: &mut Vec<CompiledRegex>,
+) -> Result<(), String> {
+    match (operator, operand) {
+        ("$all", Bson::Array(_)) => return Ok(()),
+        ("$elemMatch", Bson::Document(inner)) => {
+            if is_operator_document(inner) {
+                return validate_operators(inner, regexes);
+            }
+            return check_filter(inner, regexes);
+        }
+        ("$size", Bson::Int32(size)) if *size >= 0 => return Ok(()),
+        ("$size", Bson::Int64(size)) if *size >= 0 => return Ok(()),
+        ("$all", _) => return Err("'$all' needs an array".to_string()),
+        ("$elemMatch", _) => return Err("'$elemMatch' needs an object".to_string()),
+        _ => return Err(format!("'{}' needs a non negative integer", operator)),
+    }
+}
+
+/// Returns true when the document satisfies every key of a filter that is only
+/// matched once, such as the partial filter of an index.
+pub fn matches_filter(doc: &Document, filter: &Document) -> bool {
+    let mut regexes: Vec<CompiledRegex> = Vec::new();
+    if check_filter(filter, &mut regexes).is_err() {
+        return false;
+    }
+
+    return matches_document(doc, filter, &regexes);
+}
+
+/// Returns true when a single value satisfies a field condition, either a plain
+/// value to compare with or an object of operators.
+pub fn matches_value(value: &Bson, condition: &Bson) -> bool {
+    let mut regexes: Vec<CompiledRegex> = Vec::new();
+    let valid = match condition {
+        Bson::Document(operators) if is_operator_document(operators) => {
+            validate_operators(operators, &mut regexes)
+        }
+        Bson::RegularExpression(_) => compile_regex(condition, None, &mut regexes),
+        _ => Ok(()),
+    };
+    if valid.is_err() {
+        return false;
+    }
+
+    return matches_condition(&[value], condition, &regexes);
+}
+
+fn matches_document(doc: &Document, filter: &Document, regexes: &[CompiledRegex]) -> bool {
+    for (key, condition) in filter.iter() {
+        let matched = match key.as_str() {
+            "$text" => true,
+            "$and" => filters_of(condition).all(|filter| matches_document(doc, filter, regexes)),
+            "$or" => filters_of(condition).any(|filter| matches_document(doc, filter, regexes)),
+            "$nor" => !filters_of(condition).any(|filter| matches_document(doc, filter, regexes)),
+            _ => matches_condition(&get_path_values(doc, key), condition, regexes),
+        };
+        if !matched {
+            return false;
+        }
+    }
+
+    return true;
+}
+
+fn filters_of(operand: &Bson) -> impl Iterator<Item = &Document> {
+    let filters: &[Bson] = match operand {
+        Bson::Array(filters) => filters,
+        _ => &[],
+    };
+
+    return filters.iter().filter_map(|filter| filter.as_document());
+}
+
+fn matches_condition(values: &[&Bson], condition: &Bson, regexes: &[CompiledRegex]) -> bool {
+    match condition {
+        Bson::Document(operators) if is_operator_document(operators) => {
+            for (operator, operand) in operators.iter() {
+                let matched = match operator.as_str() {
+                    "$regex" => matches_regex(values, operand, operators.get("$options"), regexes),
+                    "$options" => true,
+                    _ => matches_operator(values, operator, operand, regexes),
+                };
+                if !matched {
+                    return false;
+                }
+            }
+            return true;
+        }
+        Bson::RegularExpression(_) => return matches_regex(values, condition, None, regexes),
+        _ => return matches_equality(values, condition),
+    }
+}
+
+fn matches_operator(
+    values: &[&Bson],
+    operator: &str,
+    operand: &Bson,
+    regexes: &[CompiledRegex],
+) -> bool {
+    match operator {
+        "$eq" => return matches_equality(values, operand),
+        "$ne" => return !matches_equality(values, operand),
+        "$gt" => return matches_ordering(values, operand, |ord| ord == Ordering::Greater),
+        "$gte" => return matches_ordering(values, operand, |ord| ord != Ordering::Less),
+        "$lt" => return matches_ordering(values, operand, |ord| ord == Ordering::Less),
+        "$lte" => return matches_ordering(values, operand, |ord| ord != Ordering::Greater),
+        "$in" => return matches_in(values, operand, regexes),
+        "$nin" => return !matches_in(values, operand, regexes),
+        "$not" => return !matches_condition(values, operand, regexes),
+        "$all" => return matches_all(values, operand, regexes),
+        "$elemMatch" => return matches_elem_match(values, operand, regexes),
+        "$size" => return matches_size(values, operand),
+        _ => return false,
+    }
+}
+
+fn matches_equality(values: &[&Bson], expected: &Bson) -> bool {
+    if values.is_empty() {
+        return *expected == Bson::Null;
+    }
+
+    return expand_arrays(values)
+        .iter()
+        .any(|value| values_equal(value, expected));
+}
+
+fn matches_ordering(values: &[&Bson], operand: &Bson, accept: fn(Ordering) -> bool) -> bool {
+    return expand_arrays(values)
+        .iter()
+        .any(|value| match compare_values(value, operand) {
+            Some(ordering) => accept(ordering),
+            None => false,
+        });
+}
+
+fn matches_in(values: &[&Bson], operand: &Bson, regexes: &[CompiledRegex]) -> bool {
+    match operand {
+        Bson::Array(candidates) => {
+            return candidates.iter().any(|candidate| match candidate {
+                Bson::RegularExpression(_) => matches_regex(values, candidate, None, regexes),
+                _ => matches_equality(values, candidate),
+            })
+        }
+        _ => return false,
+    }
+}
+
+/// Returns true when a string value (or an element of an array) matches the
+/// pattern, or when a value is that same regular expression. The pattern was
+/// compiled into `regexes` when the filter was validated.
+fn matches_regex(
+    values: &[&Bson],
+    pattern: &Bson,
+    options: Option<&Bson>,
+    regexes: &[CompiledRegex],
+) -> bool {
+    let regex = match regex_source(pattern, options) {
+        Ok((pattern, options)) => regexes
+            .iter()
+            .find(|compiled| compiled.pattern == pattern && compiled.options == options),
+        Err(_err) => None,
+    };
+    let regex = match regex {
+        Some(compiled) => &compiled.regex,
+        None => return false,
+    };
+
+    return expand_arrays(values).iter().any(|value| match value {
+        Bson::String(text) | Bson::Symbol(text) => regex.is_match(text),
+        Bson::RegularExpression(_) => *value == pattern,
+        _ => false,
+    });
+}
+
+/// Returns the pattern and options of a `$regex` operand, a pattern string or a
+/// regular expression value. `$options` replaces the options of the value.
+fn regex_source<'a>(
+    pattern: &'a Bson,
+    options: Option<&'a Bson>,
+) -> Result<(&'a str, &'a str), String> {
+    let (pattern, mut flags) = match pattern {
+        Bson::String(pattern) => (pattern.as_str(), ""),
+        Bson::RegularExpression(regex) => (regex.pattern.as_str(), regex.options.as_str()),
+        _ => return Err("'$regex' needs a string or a regular expression".to_string()),
+    };
+    match options {
+        Some(Bson::String(options)) => flags = options,
+        Some(_) => return Err("'$options' needs a string".to_string()),
+        None => {}
+    }
+
+    return Ok((pattern, flags));
+}
+
+/// Builds the regular expression of a `$regex` operand and adds it to `regexes`,
+/// unless the same pattern and options are already there.
+fn compile_regex(
+    pattern: &Bson,
+    options: Option<&Bson>,
+    regexes: &mut Vec<CompiledRegex>,
+) -> Result<(), String> {
+    let (pattern, flags) = regex_source(pattern, options)?;
+    if regexes
+        .iter()
+        .any(|compiled| compiled.pattern == pattern && compiled.options == flags)
+    {
+        return Ok(());
+    }
+
+    let mut builder = RegexBuilder::new(pattern);
+    for flag in flags.chars() {
+        match flag {
+            'i' => builder.case_insensitive(true),
+            'm' => builder.multi_line(true),
+            's' => builder.dot_matches_new_line(true),
+            'x' => builder.ignore_whitespace(true),
+            'u' => builder.unicode(true),
+            _ => return Err(format!("Unknown regular expression option '{}'", flag)),
+        };
+    }
+
+    match builder.build() {
+        Ok(regex) => {
+            regexes.push(CompiledRegex {
+                pattern: pattern.to_string(),
+                options: flags.to_string(),
+                regex,
+            });
+            return Ok(());
+        }
+        Err(err) => return Err(format!("Invalid regular expression: {}", err)),
+    }
+}
+
+fn matches_all(values: &[&Bson], operand: &Bson, regexes: &[CompiledRegex]) -> bool {
+    match operand {
+        Bson::Array(expected) if !expected.is_empty() => {
+            return expected
+                .iter()
+                .all(|item| matches_condition(values, item, regexes))
+        }
+        _ => return false,
+    }
+}
+
+fn matches_elem_match(values: &[&Bson], operand: &Bson, regexes: &[CompiledRegex]) -> bool {
+    let condition = match operand {
+        Bson::Document(condition) => condition,
+        _ => return false,
+    };
+
+    for value in values.iter() {
+        if let Bson::Array(items) = value {
+            let matched = items.iter().any(|item| {
+                if is_operator_document(condition) {
+                    return matches_condition(&[item], operand, regexes);
+                }
+                match item {
+                    Bson::Document(item) => matches_document(item, condition, regexes),
+                    _ => false,
+                }
+            });
+            if matched {
+                return true;
+            }
+        }
+    }
+
+    return false;
+}
+
+fn matches_size(values: &[&Bson], operand: &Bson) -> bool {
+    let size = match operand {
+        Bson::Int32(size) => *size as usize,
+        Bson::Int64(size) => *size as usize,
+        _ => return false,
+    };
+
+    return values.iter().any(|value| match value {
+        Bson::Array(items) => items.len() == size,
+        _ => false,
+    });
+}
+
+/// Adds the elements of every array value next to the array itself, so a
+/// condition on "tags" matches a document whose tags array contains the value.
+fn expand_arrays<'a>(values: &[&'a Bson]) -> Vec<&'a Bson> {
+    let mut expanded: Vec<&Bson> = Vec::new();
+    for value in values.iter() {
+        expanded.push(value);
+        if let Bson::Array(items) = value {
+            expanded.extend(items.iter());
+        }
+    }
+
+    return expanded;
+}
+
+/// Resolves a dotted path such as "address.city" or "tags.0" inside the document.
+/// Arrays found along the way are traversed element by element, so a path can
+/// resolve to several values.
+pub fn get_path_values<'a>(doc: &'a Document, path: &str) -> Vec<&'a Bson> {
+    let parts: Vec<&str> = path.split('.').collect();
+    let mut values: Vec<&Bson> = Vec::new();
+
+    if let Some(value) = doc.get(parts[0]) {
+        collect_path_values(value, &parts[1..], &mut values);
+    }
+
+    return values;
+}
+
+fn collect_path_values<'a>(value: &'a Bson, parts: &[&str], values: &mut Vec<&'a Bson>) {
+    if parts.is_empty() {
+        values.push(value);
+        return;
+    }
+
+    match value {
+        Bson::Document(doc) => {
+            if let Some(child) = doc.get(parts[0]) {
+                collect_path_values(child, &parts[1..], values);
+            }
+        }
+        Bson::Array(items) => {
+            if let Ok(index) = parts[0].parse::<usize>() {
+                if let Some(item) = items.get(index) {
+                    collect_path_values(item, &parts[1..], values);
+                }
+            }
+            for item in items.iter() {
+                if let Bson::Document(_) = item {
+                    collect_path_values(item, parts, values);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Compares two values of the same kind. Values of different kinds (a string
+/// against a number, for example) are not comparable and return None.
+pub fn compare_values(a: &Bson, b: &Bson) -> Option<Ordering> {
+    if !same_type_bracket(a, b) {
+        return None;
+    }
+
+    return Some(compare_bson(a, b));
+}
+
+pub fn as_integer(value: &Bson) -> Option<i64> {
+    match value {
+        Bson::Int32(number) => return Some(*number as i64),
+        Bson::Int64(number) => return Some(*number),
+        _ => return None,
+    }
+}
+
+pub fn as_number(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Int32(number) => return Some(*number as f64),
+        Bson::Int64(number) => return Some(*number as f64),
+        Bson::Double(number) => return Some(*number),
+        _ => return None,
+    }
+}
+
+fn is_operator_document(doc: &Document) -> bool {
+    match doc.keys().next() {
+        Some(key) => return key.starts_with('$'),
+        None => return false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, Bson, Document, Regex};
+
+    use super::{get_path_values, validate_filter, Filter};
+
+    fn matches(doc: Document, filter: Document) -> bool {
+        return Filter::new(filter).unwrap().matches(&doc);
+    }
+
+    #[test]
+    fn compares_numbers() {
+        let doc = doc! { "age": 30 };
+        assert!(matches(doc.clone(), doc! { "age": { "$gt": 18 } }));
+        assert!(matches(doc.clone(), doc! { "age": { "$gte": 30 } }));
+        assert!(!matches(doc.clone(), doc! { "age": { "$lt": 30 } }));
+        assert!(matches(doc.clone(), doc! { "age": { "$lte": 30 } }));
+        assert!(matches(
+            doc.clone(),
+            doc! { "age": { "$gte": 18, "$lt": 65 } }
+        ));
+        assert!(!matches(doc, doc! { "age": { "$gt": 30 } }));
+    }
+
+    #[test]
+    fn compares_numbers_of_different_types() {
+        let doc = doc! { "int": 5, "long": 5_i64, "double": 5.5 };
+        assert!(matches(doc.clone(), doc! { "int": 5.0 }));
+        assert!(matches(doc.clone(), doc! { "long": { "$eq": 5 } }));
+        assert!(matches(doc.clone(), doc! { "double": { "$gt": 5_i64 } }));
+        assert!(matches(doc.clone(), doc! { "int": { "$lt": 5.5 } }));
+        assert!(matches(doc, doc! { "int": { "$in": [1.0, 5.0] } }));
+    }
+
+    #[test]
+    fn doesnt_compare_values_of_different_kinds() {
+        let doc = doc! { "value": "10" };
+        assert!(!matches(doc.clone(), doc! { "value": { "$gt": 5 } }));
+        assert!(!matches(doc.clone(), doc! { "value": { "$lt": 5 } }));
+        assert!(!matches(doc, doc! { "value": 10 }));
+    }
+
+    #[test]
+    fn matches_ne_in_and_nin() {
+        let doc = doc! { "status": "active" };
+        assert!(matches(
+            doc.clone(),
+            doc! { "status": { "$ne": "pending" } }
+        ));
+        assert!(!matches(
+            doc.clone(),
+            doc! { "status": { "$ne": "active" } }
+        ));
+        assert!(matches(
+            doc.clone(),
+            doc! { "status": { "$in": ["active", "pending"] } }
+        ));
+        assert!(!matches(
+            doc.clone(),
+            doc! { "status": { "$nin": ["active"] } }
+        ));
+        assert!(matches(doc, doc! { "status": { "$nin": [] } }));
+    }
+
+    #[test]
+    fn missing_fields_equal_null() {
+        let doc = doc! { "name": "Ada" };
+        assert!(matches(doc.clone(), doc! { "age": null }));
+        assert!(matches(doc.clone(), doc! { "age": { "$ne": 30 } }));
+        assert!(!matches(doc.clone(), doc! { "age": { "$gt": 0 } }));
+        assert!(matches(doc, doc! { "age": { "$in": [null] } }));
+    }
+
+    #[test]
+    fn rejects_invalid_comparisons() {
+        assert!(validate_filter(&doc! { "age": { "$between": [1, 2] } }).is_err());
+        assert!(validate_filter(&doc! { "age": { "$in": 5 } }).is_err());
+        assert!(validate_filter(&doc! { "age": { "$nin": "a" } }).is_err());
+        assert!(validate_filter(&doc! { "age": { "$gt": 5 } }).is_ok());
+    }
+
+    #[test]
+    fn combines_filters() {
+        let doc = doc! { "status": "draft", "owner": "me", "age": 20 };
+        let or = doc! { "$or": [{ "status": "published" }, { "owner": "me" }] };
+        assert!(matches(doc.clone(), or));
+        let and = doc! { "$and": [{ "status": "draft" }, { "age": { "$gt": 30 } }] };
+        assert!(!matches(doc.clone(), and));
+        let nor = doc! { "$nor": [{ "status": "published" }, { "owner": "you" }] };
+        assert!(matches(doc.clone(), nor));
+        let not = doc! { "age": { "$not": { "$gte": 18 } } };
+        assert!(!matches(doc.clone(), not));
+        let nested = doc! { "$or": [{ "$and": [{ "owner": "me" }, { "age": 20 }] }] };
+        assert!(matches(doc, nested));
+    }
+
+    #[test]
+    fn not_matches_missing_fields() {
+        assert!(matches(doc! {}, doc! { "age": { "$not": { "$gt": 5 } } }));
+    }
+
+    #[test]
+    fn rejects_invalid_logical_operators() {
+        assert!(validate_filter(&doc! { "$or": [] }).is_err());
+        assert!(validate_filter(&doc! { "$and": { "a": 1 } }).is_err());
+        assert!(validate_filter(&doc! { "$nor": [1] }).is_err());
+        assert!(validate_filter(&doc! { "$xor": [{ "a": 1 }] }).is_err());
+        assert!(validate_filter(&doc! { "a": { "$not": 5 } }).is_err());
+    }
+
+    #[test]
+    fn matches_dotted_paths() {
+        let doc = doc! {
+            "address": { "city": "Oslo", "geo": { "lat": 59 } },
+            "tags": ["admin", "dev"],
+            "orders": [{ "total": 10 }, { "total": 25 }],
+        };
+        assert!(matches(doc.clone(), doc! { "address.city": "Oslo" }));
+        assert!(matches(
+            doc.clone(),
+            doc! { "address.geo.lat": { "$gt": 50 } }
+        ));
+        assert!(matches(doc.clone(), doc! { "tags.0": "admin" }));
+        assert!(!matches(doc.clone(), doc! { "tags.1": "admin" }));
+        assert!(matches(doc.clone(), doc! { "orders.total": 25 }));
+        assert!(matches(doc.clone(), doc! { "orders.1.total": 25 }));
+        assert!(matches(doc.clone(), doc! { "address.zip": null }));
+        assert!(!matches(doc, doc! { "address.city.name": "Oslo" }));
+    }
+
+    #[test]
+    fn resolves_paths_through_arrays() {
+        let doc = doc! { "a": [{ "b": 1 }, { "b": [2, 3] }, 4] };
+        let values = get_path_values(&doc, "a.b");
+        assert_eq!(
+            values,
+            vec![&Bson::Int32(1), &Bson::Array(vec![2.into(), 3.into()])]
+        );
+        assert_eq!(get_path_values(&doc, "a.2"), vec![&Bson::Int32(4)]);
+        assert!(get_path_values(&doc, "c").is_empty());
+    }
+
+    #[test]
+    fn matches_array_members() {
+        let doc = doc! { "tags": ["admin", "dev"], "scores": [3, 8] };
+        assert!(matches(doc.clone(), doc! { "tags": "dev" }));
+        assert!(matches(doc.clone(), doc! { "tags": ["admin", "dev"] }));
+        assert!(!matches(doc.clone(), doc! { "tags": ["dev", "admin"] }));
+        assert!(matches(doc.clone(), doc! { "scores": { "$gt": 5 } }));
+        assert!(!matches(doc, doc! { "tags": { "$ne": "dev" } }));
+    }
+
+    #[test]
+    fn matches_array_operators() {
+        let doc = doc! {
+            "tags": ["admin", "dev", "ops"],
+            "results": [{ "product": "a", "score": 4 }, { "product": "b", "score": 9 }],
+            "scores": [3, 8],
+        };
+        assert!(matches(
+            doc.clone(),
+            doc! { "tags": { "$all": ["ops", "admin"] } }
+        ));
+        assert!(!matches(
+            doc.clone(),
+            doc! { "tags": { "$all": ["ops", "qa"] } }
+        ));
+        assert!(matches(doc.clone(), doc! { "tags": { "$size": 3 } }));
+        assert!(!matches(doc.clone(), doc! { "scores": { "$size": 3 } }));
+        let both = doc! { "results": { "$elemMatch": { "product": "a", "score": { "$gt": 5 } } } };
+        assert!(!matches(doc.clone(), both));
+        let one = doc! { "results": { "$elemMatch": { "product": "b", "score": { "$gt": 5 } } } };
+        assert!(matches(doc.clone(), one));
+        let range = doc! { "scores": { "$elemMatch": { "$gt": 4, "$lt": 6 } } };
+        assert!(!matches(doc, range));
+    }
+
+    #[test]
+    fn rejects_invalid_array_operators() {
+        assert!(validate_filter(&doc! { "a": { "$all": 1 } }).is_err());
+        assert!(validate_filter(&doc! { "a": { "$size": -1 } }).is_err());
+        assert!(validate_filter(&doc! { "a": { "$size": "2" } }).is_err());
+        assert!(validate_filter(&doc! { "a": { "$elemMatch": [1] } }).is_err());
+    }
+
+    fn regex(pattern: &str, options: &str) -> Bson {
+        return Bson::RegularExpression(Regex {
+            pattern: pattern.to_string(),
+            options: options.to_string(),
+        });
+    }
+
+    #[test]
+    fn matches_regular_expressions() {
+        let doc = doc! { "name": "Alice", "tags": ["red", "Blue"], "age": 30 };
+        assert!(matches(doc.clone(), doc! { "name": { "$regex": "^Al" } }));
+        assert!(!matches(doc.clone(), doc! { "name": { "$regex": "^al" } }));
+        assert!(matches(
+            doc.clone(),
+            doc! { "name": { "$regex": "^al", "$options": "i" } }
+        ));
+        assert!(matches(doc.clone(), doc! { "name": regex("ice$", "") }));
+        // `$options` replaces the options of a regular expression value
+        let replaced = doc! { "name": { "$regex": regex("^AL", "i"), "$options": "" } };
+        assert!(!matches(doc.clone(), replaced));
+        assert!(matches(doc.clone(), doc! { "tags": regex("^blue$", "i") }));
+        assert!(matches(
+            doc.clone(),
+            doc! { "tags": { "$in": [regex("^r", ""), "x"] } }
+        ));
+        assert!(!matches(doc.clone(), doc! { "age": { "$regex": "30" } }));
+        assert!(matches(doc, doc! { "name": { "$not": regex("^B", "") } }));
+    }
+
+    #[test]
+    fn matches_stored_regular_expressions() {
+        let doc = doc! { "pattern": regex("^a", "i") };
+        assert!(matches(doc.clone(), doc! { "pattern": regex("^a", "i") }));
+        assert!(!matches(doc, doc! { "pattern": regex("^a", "") }));
+    }
+
+    #[test]
+    fn rejects_invalid_regular_expressions() {
+        assert!(validate_filter(&doc! { "a": { "$regex": "(" } }).is_err());
+        assert!(validate_filter(&doc! { "a": { "$regex": "a", "$options": "q" } }).is_err());
+        assert!(validate_filter(&doc! { "a": { "$regex": "a", "$options": 1 } }).is_err());
+        assert!(validate_filter(&doc! { "a": { "$options": "i" } }).is_err());
+        assert!(validate_filter(&doc! { "a": { "$regex": 1 } }).is_err());
+        assert!(validate_filter(&doc! { "a": { "$in": [regex("[", "")] } }).is_err());
+    }
+}
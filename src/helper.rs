@@ -0,0 +1,392 @@
+use std::{borrow::Cow, cell::RefCell, iter::Peekable, str::CharIndices};
+
+use rustyline::{
+    completion::{Completer, FilenameCompleter},
+    highlight::Highlighter,
+    hint::Hinter,
+    validate::{ValidationContext, ValidationResult, Validator},
+    Context, Helper, Result,
+};
+
+use crate::{
+    commands::DOT_COMMANDS, lexer::statement_is_complete, output::OUTPUT_MODES,
+    statement::STATEMENT_KEYWORDS, Database,
+};
+
+/// How many documents of each collection are read to find its field names.
+const FIELD_SAMPLE_SIZE: usize = 100;
+
+/// The ANSI colors of the parts of a statement.
+const KEYWORD_COLOR: &str = "\x1b[1;34m";
+const COLLECTION_COLOR: &str = "\x1b[33m";
+const STRING_COLOR: &str = "\x1b[32m";
+const OPERATOR_COLOR: &str = "\x1b[36m";
+const LITERAL_COLOR: &str = "\x1b[35m";
+const RESET_COLOR: &str = "\x1b[0m";
+
+/// Lets rustyline know how the input of the REPL is written.
+pub struct ReplHelper {
+    /// The collections of the open database with the fields sampled from
+    /// their documents, refreshed before every statement.
+    collections: Vec<(String, Vec<String>)>,
+    filenames: FilenameCompleter,
+    /// The statement the validator is waiting to be finished, so that it can
+    /// be reported when the input ends before it is.
+    unfinished: RefCell<Option<String>>,
+}
+
+impl ReplHelper {
+    pub fn new() -> Self {
+        Self {
+            collections: Vec::new(),
+            filenames: FilenameCompleter::new(),
+            unfinished: RefCell::new(None),
+        }
+    }
+
+    pub fn take_unfinished(&self) -> Option<String> {
+        return self.unfinished.take();
+    }
+
+    pub fn refresh(&mut self, database: &Database) {
+        self.collections.clear();
+        for name in database.get_collection_names() {
+            let fields = match database.find_collection(&name) {
+                Some(collection) => collection.sample_fields(FIELD_SAMPLE_SIZE),
+                None => Vec::new(),
+            };
+            self.collections.push((name, fields));
+        }
+    }
+
+    fn is_collection(&self, word: &str) -> bool {
+        return self.collections.iter().any(|(name, _fields)| name == word);
+    }
+
+    /// Colors the keyword or dot command, the collection, and the strings,
+    /// operators, numbers, booleans and nulls of the json values.
+    fn highlight_statement(&self, line: &str) -> String {
+        let mut output = String::new();
+        let mut chars = line.char_indices().peekable();
+        let mut words: Vec<&str> = Vec::new();
+        let mut in_json = false;
+
+        while let Some((start, c)) = chars.next() {
+            if c.is_whitespace() {
+                output.push(c);
+                continue;
+            }
+
+            if !in_json && c != '{' && c != '[' {
+                let end = read_while(&mut chars, line, |c| {
+                    !c.is_whitespace() && c != '{' && c != '['
+                });
+                let word = &line[start..end];
+                let collection_index = match words.first() {
+                    Some(&"explain") => 2,
+                    _ => 1,
+                };
+                let color = if words.is_empty()
+                    && (STATEMENT_KEYWORDS.contains(&word)
+                        || DOT_COMMANDS
+                            .iter()
+                            .any(|command| command.get_name() == word))
+                {
+                    Some(KEYWORD_COLOR)
+                } else if words.len() == collection_index && self.is_collection(word) {
+                    Some(COLLECTION_COLOR)
+                } else {
+                    None
+                };
+                words.push(word);
+                push_colored(&mut output, word, color);
+                continue;
+            }
+
+            in_json = true;
+            match c {
+                '"' => {
+                    let mut escaped = false;
+                    let end = read_while(&mut chars, line, |c| {
+                        let inside = escaped || c != '"';
+                        escaped = !escaped && c == '\\';
+                        inside
+                    });
+                    // The closing quote
+                    let end = match chars.next() {
+                        Some((i, _quote)) => i + 1,
+                        None => end,
+                    };
+                    let text = &line[start..end];
+                    let color = match text.starts_with("\"$") {
+                        true => OPERATOR_COLOR,
+                        false => STRING_COLOR,
+                    };
+                    push_colored(&mut output, text, Some(color));
+                }
+                '-' | '0'..='9' => {
+                    let end = read_while(&mut chars, line, |c| {
+                        c.is_ascii_digit() || ".eE+-".contains(c)
+                    });
+                    push_colored(&mut output, &line[start..end], Some(LITERAL_COLOR));
+                }
+                _ if c.is_alphabetic() => {
+                    let end = read_while(&mut chars, line, char::is_alphanumeric);
+                    let word = &line[start..end];
+                    let color = match word {
+                        "true" | "false" | "null" => Some(LITERAL_COLOR),
+                        _ => None,
+                    };
+                    push_colored(&mut output, word, color);
+                }
+                _ => output.push(c),
+            }
+        }
+
+        return output;
+    }
+
+    fn collection_names(&self) -> Vec<String> {
+        return self
+            .collections
+            .iter()
+            .map(|(name, _fields)| name.to_owned())
+            .collect();
+    }
+
+    fn field_names(&self, collection: Option<&&str>) -> Vec<String> {
+        for (name, fields) in self.collections.iter() {
+            if Some(&name.as_str()) == collection {
+                return fields.clone();
+            }
+        }
+
+        return Vec::new();
+    }
+
+    /// Returns what can be written at `start`, the beginning of the word under
+    /// the cursor: a keyword or dot command first, then a collection name, and
+    /// field names for `distinct` or for the keys of a json object.
+    fn candidates(&self, before: &str, start: usize) -> Vec<String> {
+        let to_strings = |words: &[&str]| words.iter().map(|word| word.to_string()).collect();
+        let dot_commands: Vec<String> = DOT_COMMANDS
+            .iter()
+            .map(|command| command.get_name().to_string())
+            .collect();
+
+        let json_start = before.find(['{', '[']);
+        let words: Vec<&str> = before[..json_start.unwrap_or(before.len())]
+            .split_whitespace()
+            .collect();
+        let collection = match words.first() {
+            Some(&"explain") => words.get(2),
+            _ => words.get(1),
+        };
+
+        if json_start.is_some() {
+            let head = before[..start].trim_end();
+            let head = head.strip_suffix('"').unwrap_or(head).trim_end();
+            if head.ends_with('{') || head.ends_with(',') {
+                return self.field_names(collection);
+            }
+            return Vec::new();
+        }
+
+        let index = match before.ends_with(char::is_whitespace) || words.is_empty() {
+            true => words.len(),
+            false => words.len() - 1,
+        };
+        match (index, words.first()) {
+            (0, _) if before.trim_start().starts_with('.') => return dot_commands,
+            (0, _) => return to_strings(&STATEMENT_KEYWORDS),
+            (1, Some(&".help")) => {
+                let keywords: Vec<String> = to_strings(&STATEMENT_KEYWORDS);
+                return [dot_commands, keywords].concat();
+            }
+            (1, Some(&".mode")) => return to_strings(&OUTPUT_MODES),
+            (1, Some(&".timer")) => return to_strings(&["on", "off"]),
+            (1, Some(&".stats")) => return self.collection_names(),
+            (1, Some(&"explain")) => return to_strings(&["find"]),
+            (1, Some(keyword)) if takes_collection(keyword) => return self.collection_names(),
+            (2, Some(&"explain")) => return self.collection_names(),
+            (2, Some(&"distinct")) => return self.field_names(collection),
+            _ => {}
+        }
+
+        return Vec::new();
+    }
+}
+
+/// Returns true for the statements whose second word is an existing collection.
+fn takes_collection(keyword: &str) -> bool {
+    return STATEMENT_KEYWORDS.contains(&keyword)
+        && !["create", "peek", "commit"].contains(&keyword);
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Result<(usize, Vec<String>)> {
+        let before = &line[..pos];
+        let words: Vec<&str> = before.split_whitespace().collect();
+        let naming_file = words.len() > 1 || before.ends_with(char::is_whitespace);
+        if matches!(words.first(), Some(&".open" | &".read" | &".save")) && naming_file {
+            let (start, pairs) = self.filenames.complete(line, pos, ctx)?;
+            return Ok((
+                start,
+                pairs.into_iter().map(|pair| pair.replacement).collect(),
+            ));
+        }
+
+        // The word under the cursor starts after a space, bracket, comma, colon or quote
+        let start = match before
+            .char_indices()
+            .rev()
+            .find(|(_i, c)| c.is_whitespace() || "{[,:\"".contains(*c))
+        {
+            Some((i, c)) => i + c.len_utf8(),
+            None => 0,
+        };
+
+        let partial = &before[start..];
+        let mut candidates: Vec<String> = self
+            .candidates(before, start)
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(partial))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+
+        return Ok((start, candidates));
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+/// Moves past the characters accepted by the function and returns the byte
+/// offset of the first one it refuses.
+fn read_while(
+    chars: &mut Peekable<CharIndices>,
+    line: &str,
+    mut accept: impl FnMut(char) -> bool,
+) -> usize {
+    while let Some((i, c)) = chars.peek() {
+        if !accept(*c) {
+            return *i;
+        }
+        chars.next();
+    }
+
+    return line.len();
+}
+
+fn push_colored(output: &mut String, text: &str, color: Option<&str>) {
+    match color {
+        Some(color) => output.push_str(&format!("{}{}{}", color, text, RESET_COLOR)),
+        None => output.push_str(text),
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        return Cow::Owned(self.highlight_statement(line));
+    }
+
+    /// Every character can change the colors, such as a quote opening a string.
+    fn highlight_char(&self, _line: &str, _pos: usize, _forced: bool) -> bool {
+        return true;
+    }
+}
+
+impl Validator for ReplHelper {
+    /// Keeps Enter adding lines to a statement until its brackets and strings
+    /// are closed or it ends in `;`. Dot commands always fit in one line.
+    fn validate(&self, ctx: &mut ValidationContext) -> Result<ValidationResult> {
+        let input = ctx.input();
+        let trimmed = input.trim_start();
+        if trimmed.starts_with('.') || statement_is_complete(trimmed) {
+            self.unfinished.replace(None);
+            return Ok(ValidationResult::Valid(None));
+        }
+
+        self.unfinished.replace(Some(input.to_string()));
+        return Ok(ValidationResult::Incomplete);
+    }
+}
+
+impl Helper for ReplHelper {}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+
+    use super::{ReplHelper, COLLECTION_COLOR, KEYWORD_COLOR, RESET_COLOR, STRING_COLOR};
+    use crate::{collection::Collection, database::Database};
+
+    fn helper() -> ReplHelper {
+        let mut users = Collection::new("users".to_string());
+        let documents = [
+            doc! { "name": "a", "address": { "city": "Oslo" } },
+            doc! { "name": "b", "age": 3 },
+        ];
+        for doc in documents {
+            assert!(users.add_to_collection(doc).is_ok());
+        }
+        let database = Database::new("test".to_string(), vec![users]);
+        let mut helper = ReplHelper::new();
+        helper.refresh(&database);
+        return helper;
+    }
+
+    fn candidates(helper: &ReplHelper, before: &str) -> Vec<String> {
+        let start = match before.rfind([' ', '{', ',', '"']) {
+            Some(i) => i + 1,
+            None => 0,
+        };
+        return helper.candidates(before, start);
+    }
+
+    #[test]
+    fn completes_keywords_and_collections() {
+        let helper = helper();
+        assert!(candidates(&helper, "fi").contains(&"find".to_string()));
+        assert!(candidates(&helper, ".he").contains(&".help".to_string()));
+        assert_eq!(candidates(&helper, "find "), vec!["users"]);
+        assert_eq!(candidates(&helper, "explain "), vec!["find"]);
+        assert_eq!(candidates(&helper, "explain find "), vec!["users"]);
+        assert!(candidates(&helper, "create ").is_empty());
+        assert!(candidates(&helper, ".mode ").contains(&"pretty".to_string()));
+        assert_eq!(candidates(&helper, ".timer "), vec!["on", "off"]);
+    }
+
+    #[test]
+    fn completes_field_names() {
+        let helper = helper();
+        let fields = vec!["_id", "name", "address.city", "address", "age"];
+        assert_eq!(candidates(&helper, "distinct users "), fields);
+        assert_eq!(candidates(&helper, "find users {\"na"), fields);
+        assert_eq!(candidates(&helper, "find users {\"age\": 1, "), fields);
+        assert!(candidates(&helper, "find users {\"age\": ").is_empty());
+        assert!(candidates(&helper, "find missing {").is_empty());
+    }
+
+    #[test]
+    fn highlights_statements() {
+        let helper = helper();
+        let highlighted = helper.highlight_statement("find users {\"a\": \"b c\"}");
+        let expected = format!(
+            "{k}find{r} {c}users{r} {{{s}\"a\"{r}: {s}\"b c\"{r}}}",
+            k = KEYWORD_COLOR,
+            c = COLLECTION_COLOR,
+            s = STRING_COLOR,
+            r = RESET_COLOR
+        );
+        assert_eq!(highlighted, expected);
+        assert_eq!(
+            helper.highlight_statement("find others"),
+            format!("{}find{} others", KEYWORD_COLOR, RESET_COLOR)
+        );
+    }
+}
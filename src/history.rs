@@ -0,0 +1,127 @@
+use std::{env, path::PathBuf};
+
+use regex::Regex;
+
+/// How many statements are kept when `DOCLITE_HISTORY_SIZE` isnt set.
+const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// Statements matching this are left out of the history file when
+/// `DOCLITE_HISTORY_IGNORE` isnt set.
+const DEFAULT_HISTORY_IGNORE: &str = r"(?i)passw(or)?d|secret|token|api_?key|credential";
+
+/// Where the statements of the REPL are saved between sessions, how many are
+/// kept and which are never saved. Read from the environment:
+/// `DOCLITE_HISTORY` is the file (empty turns the history off),
+/// `DOCLITE_HISTORY_SIZE` the number of statements kept and
+/// `DOCLITE_HISTORY_IGNORE` a regular expression (empty saves everything).
+pub struct HistoryOptions {
+    path: Option<PathBuf>,
+    max_size: usize,
+    ignore: HistoryIgnore,
+}
+
+/// Which statements are left out of the history file.
+enum HistoryIgnore {
+    Nothing,
+    Everything,
+    Matching(Regex),
+}
+
+impl HistoryOptions {
+    pub fn from_env() -> Self {
+        let path = match env::var_os("DOCLITE_HISTORY") {
+            Some(path) if path.is_empty() => None,
+            Some(path) => Some(PathBuf::from(path)),
+            None => home_dir().map(|home| home.join(".doclite_history")),
+        };
+
+        let max_size = match env::var("DOCLITE_HISTORY_SIZE") {
+            Ok(size) => match size.trim().parse::<usize>() {
+                Ok(size) => size,
+                Err(_e) => {
+                    eprintln!(
+                        "Invalid DOCLITE_HISTORY_SIZE '{}', keeping {} statements",
+                        size, DEFAULT_HISTORY_SIZE
+                    );
+                    DEFAULT_HISTORY_SIZE
+                }
+            },
+            Err(_e) => DEFAULT_HISTORY_SIZE,
+        };
+
+        let pattern =
+            env::var("DOCLITE_HISTORY_IGNORE").unwrap_or(DEFAULT_HISTORY_IGNORE.to_string());
+        let ignore = match pattern.is_empty() {
+            true => HistoryIgnore::Nothing,
+            false => match Regex::new(&pattern) {
+                Ok(ignore) => HistoryIgnore::Matching(ignore),
+                Err(_e) => {
+                    eprintln!("Invalid DOCLITE_HISTORY_IGNORE, every statement is left out");
+                    HistoryIgnore::Everything
+                }
+            },
+        };
+
+        Self {
+            path,
+            max_size,
+            ignore,
+        }
+    }
+
+    pub fn get_path(&self) -> Option<&PathBuf> {
+        return self.path.as_ref();
+    }
+
+    pub fn get_max_size(&self) -> usize {
+        return self.max_size;
+    }
+
+    /// Returns false for the statements that look like they hold a secret.
+    pub fn should_save(&self, entry: &str) -> bool {
+        match &self.ignore {
+            HistoryIgnore::Nothing => return true,
+            HistoryIgnore::Everything => return false,
+            HistoryIgnore::Matching(ignore) => return !ignore.is_match(entry),
+        }
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    return env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from);
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::{HistoryIgnore, HistoryOptions, DEFAULT_HISTORY_IGNORE, DEFAULT_HISTORY_SIZE};
+
+    fn options(ignore: HistoryIgnore) -> HistoryOptions {
+        return HistoryOptions {
+            path: None,
+            max_size: DEFAULT_HISTORY_SIZE,
+            ignore,
+        };
+    }
+
+    #[test]
+    fn leaves_out_secrets_by_default() {
+        let options = options(HistoryIgnore::Matching(
+            Regex::new(DEFAULT_HISTORY_IGNORE).unwrap(),
+        ));
+        assert!(options.should_save("find users {\"age\": 3}"));
+        assert!(!options.should_save("insert users {\"Password\": \"x\"}"));
+        assert!(!options.should_save("insert keys {\"api_key\": \"x\"}"));
+        assert!(!options.should_save("update users {} {\"$set\": {\"token\": 1}}"));
+    }
+
+    #[test]
+    fn saves_everything_or_nothing() {
+        assert!(options(HistoryIgnore::Nothing).should_save("insert c {\"passwd\": 1}"));
+        assert!(!options(HistoryIgnore::Everything).should_save("find c"));
+    }
+}
@@ -0,0 +1,604 @@
+use std::{cmp::Ordering, collections::BTreeMap, ops::Bound};
+
+use bson::{Bson, Document};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    filter::{as_number, get_path_values, matches_filter, validate_filter},
+    ordering::compare_bson,
+    text::is_text_index,
+};
+
+/// The values of the indexed fields of a document, ordered like `compare_bson`.
+#[derive(Clone, Debug)]
+pub struct IndexKey(Vec<Bson>);
+
+impl Ord for IndexKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        for (a, b) in self.0.iter().zip(other.0.iter()) {
+            let ordering = compare_bson(a, b);
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+
+        return self.0.len().cmp(&other.0.len());
+    }
+}
+
+impl PartialOrd for IndexKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl PartialEq for IndexKey {
+    fn eq(&self, other: &Self) -> bool {
+        return self.cmp(other) == Ordering::Equal;
+    }
+}
+
+impl Eq for IndexKey {}
+
+/// The part of an index that a query has to read: some exact values of the
+/// first indexed field, or a range of them.
+#[allow(clippy::large_enum_variant)]
+pub enum IndexBounds {
+    Points(Vec<Bson>),
+    Range(Bound<Bson>, Bound<Bson>),
+}
+
+impl IndexBounds {
+    fn includes_null(&self) -> bool {
+        match self {
+            IndexBounds::Points(values) => return values.contains(&Bson::Null),
+            IndexBounds::Range(lower, upper) => {
+                for bound in [lower, upper] {
+                    if let Bound::Included(Bson::Null) | Bound::Excluded(Bson::Null) = bound {
+                        return true;
+                    }
+                }
+                return false;
+            }
+        }
+    }
+}
+
+/// An ordered index over one or more fields of the documents of a collection.
+/// Only the definition is saved in the database file, the entries are rebuilt
+/// when the database is opened.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Index {
+    name: String,
+    keys: Document,
+    #[serde(default)]
+    unique: bool,
+    #[serde(default)]
+    sparse: bool,
+    #[serde(default, rename = "partialFilterExpression")]
+    partial_filter: Option<Document>,
+    #[serde(skip)]
+    entries: BTreeMap<IndexKey, Vec<usize>>,
+    #[serde(skip)]
+    multikey: bool,
+}
+
+/// Checks that every field of the index specification is 1 (ascending) or -1
+/// (descending), or that all of them are "text" for a text index.
+pub fn validate_index_keys(keys: &Document) -> Result<(), String> {
+    if keys.is_empty() {
+        return Err("The index needs at least one field".to_string());
+    }
+    if is_text_index(keys) {
+        return Ok(());
+    }
+
+    for (field, direction) in keys.iter() {
+        match as_number(direction) {
+            Some(direction) if direction == 1.0 || direction == -1.0 => {}
+            _ => return Err(format!("The direction of '{}' must be 1 or -1", field)),
+        }
+    }
+
+    return Ok(());
+}
+
+/// Checks the options of a new index: `unique` and `sparse` are booleans and
+/// `partialFilterExpression` is a filter. Sparse and partial indexes cant be combined.
+pub fn validate_index_options(options: &Document) -> Result<(), String> {
+    for (key, value) in options.iter() {
+        match (key.as_str(), value) {
+            ("unique" | "sparse", Bson::Boolean(_)) => {}
+            ("unique" | "sparse", _) => return Err(format!("'{}' must be true or false", key)),
+            ("partialFilterExpression", Bson::Document(filter)) => validate_filter(filter)?,
+            ("partialFilterExpression", _) => {
+                return Err("'partialFilterExpression' must be a filter document".to_string())
+            }
+            _ => return Err(format!("Unknown index option '{}'", key)),
+        }
+    }
+
+    if options.get_bool("sparse").unwrap_or(false)
+        && options.contains_key("partialFilterExpression")
+    {
+        return Err("An index cant be both sparse and partial".to_string());
+    }
+
+    return Ok(());
+}
+
+/// Builds the default name of an index, such as "age_1_name_-1" or "title_text".
+pub fn index_name(keys: &Document) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    for (field, direction) in keys.iter() {
+        match direction.as_str() {
+            Some(kind) => parts.push(format!("{}_{}", field, kind)),
+            None => {
+                let direction = as_number(direction).unwrap_or(1.0) as i64;
+                parts.push(format!("{}_{}", field, direction));
+            }
+        }
+    }
+
+    return parts.join("_");
+}
+
+impl Index {
+    pub fn new(name: String, keys: Document, options: &Document) -> Self {
+        Self {
+            name,
+            keys,
+            unique: options.get_bool("unique").unwrap_or(false),
+            sparse: options.get_bool("sparse").unwrap_or(false),
+            partial_filter: options
+                .get_document("partialFilterExpression")
+                .ok()
+                .cloned(),
+            entries: BTreeMap::new(),
+            multikey: false,
+        }
+    }
+
+    pub fn get_name(&self) -> String {
+        return self.name.to_owned();
+    }
+
+    fn first_field(&self) -> &str {
+        match self.keys.keys().next() {
+            Some(field) => return field,
+            None => return "",
+        }
+    }
+
+    pub fn rebuild(&mut self, documents: &[Document]) {
+        self.entries.clear();
+        self.multikey = false;
+        for (position, doc) in documents.iter().enumerate() {
+            self.add_document(doc, position);
+        }
+    }
+
+    /// Returns false for the documents a sparse or partial index leaves out.
+    fn covers(&self, doc: &Document) -> bool {
+        if self.sparse
+            && self
+                .keys
+                .keys()
+                .all(|field| get_path_values(doc, field).is_empty())
+        {
+            return false;
+        }
+        if let Some(filter) = &self.partial_filter {
+            return matches_filter(doc, filter);
+        }
+
+        return true;
+    }
+
+    pub fn add_document(&mut self, doc: &Document, position: usize) {
+        if !self.covers(doc) {
+            return;
+        }
+
+        for field in self.keys.keys() {
+            let values = get_path_values(doc, field);
+            if values.iter().any(|value| matches!(value, Bson::Array(_))) {
+                self.multikey = true;
+            }
+        }
+        for key in self.document_keys(doc) {
+            let positions = self.entries.entry(key).or_default();
+            if !positions.contains(&position) {
+                positions.push(position);
+            }
+        }
+    }
+
+    pub fn remove_document(&mut self, doc: &Document, position: usize) {
+        for key in self.document_keys(doc) {
+            if let Some(positions) = self.entries.get_mut(&key) {
+                positions.retain(|item| *item != position);
+                if positions.is_empty() {
+                    self.entries.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Returns every key of the document. Arrays add a key for each element
+    /// besides the whole array, so compound indexes get one key per combination.
+    fn document_keys(&self, doc: &Document) -> Vec<IndexKey> {
+        let mut keys: Vec<Vec<Bson>> = vec![Vec::new()];
+        for field in self.keys.keys() {
+            let mut values: Vec<Bson> = Vec::new();
+            for value in get_path_values(doc, field) {
+                if let Bson::Array(items) = value {
+                    values.extend(items.iter().cloned());
+                }
+                values.push(value.clone());
+            }
+            if values.is_empty() {
+                values.push(Bson::Null);
+            }
+
+            let mut combined: Vec<Vec<Bson>> = Vec::new();
+            for key in keys.iter() {
+                for value in values.iter() {
+                    let mut key = key.clone();
+                    key.push(value.clone());
+                    combined.push(key);
+                }
+            }
+            keys = combined;
+        }
+
+        return keys.into_iter().map(IndexKey).collect();
+    }
+
+    /// For a unique index, describes the key of the document that another
+    /// document already has. The document at `position` is the one being replaced.
+    pub fn conflicting_key(&self, doc: &Document, position: Option<usize>) -> Option<String> {
+        if !self.unique || !self.covers(doc) {
+            return None;
+        }
+
+        for key in self.document_keys(doc) {
+            if let Some(positions) = self.entries.get(&key) {
+                if positions.iter().any(|item| Some(*item) != position) {
+                    return Some(self.describe_key(&key));
+                }
+            }
+        }
+
+        return None;
+    }
+
+    /// For a unique index, describes the first key shared by several documents.
+    pub fn duplicate_key(&self) -> Option<String> {
+        if !self.unique {
+            return None;
+        }
+
+        for (key, positions) in self.entries.iter() {
+            if positions.len() > 1 {
+                return Some(self.describe_key(key));
+            }
+        }
+
+        return None;
+    }
+
+    fn describe_key(&self, key: &IndexKey) -> String {
+        let mut fields = Document::new();
+        for (field, value) in self.keys.keys().zip(key.0.iter()) {
+            fields.insert(field, value.clone());
+        }
+
+        return format!("{} in index {}", fields, self.name);
+    }
+
+    /// Returns the bounds this index can use for the query, looking at the
+    /// conditions on its first field. None means the index cant help.
+    pub fn bounds_for(&self, query: &Document) -> Option<IndexBounds> {
+        // A partial index only holds the documents of its filter, so the query
+        // has to repeat every condition of it
+        if let Some(filter) = &self.partial_filter {
+            if filter
+                .iter()
+                .any(|(key, condition)| query.get(key) != Some(condition))
+            {
+                return None;
+            }
+        }
+
+        let bounds = self.first_field_bounds(query)?;
+
+        // A sparse index has no entries for missing fields, which match null
+        if self.sparse && bounds.includes_null() {
+            return None;
+        }
+
+        return Some(bounds);
+    }
+
+    fn first_field_bounds(&self, query: &Document) -> Option<IndexBounds> {
+        let condition = query.get(self.first_field())?;
+        let operators = match condition {
+            Bson::Document(operators) if operators.keys().any(|key| key.starts_with('$')) => {
+                operators
+            }
+            // A regular expression matches strings, not just itself
+            Bson::RegularExpression(_) => return None,
+            _ => return Some(IndexBounds::Points(vec![condition.clone()])),
+        };
+
+        if let Some(value) = operators.get("$eq") {
+            return Some(IndexBounds::Points(vec![value.clone()]));
+        }
+        if let Some(Bson::Array(values)) = operators.get("$in") {
+            if values
+                .iter()
+                .any(|value| matches!(value, Bson::RegularExpression(_)))
+            {
+                return None;
+            }
+            return Some(IndexBounds::Points(values.clone()));
+        }
+
+        let mut lower: Bound<Bson> = Bound::Unbounded;
+        let mut upper: Bound<Bson> = Bound::Unbounded;
+        for (operator, value) in operators.iter() {
+            // With arrays each bound can be met by a different element, so a
+            // multikey index only uses the first one
+            let has_bound =
+                !matches!(lower, Bound::Unbounded) || !matches!(upper, Bound::Unbounded);
+            if self.multikey && has_bound {
+                break;
+            }
+            match operator.as_str() {
+                "$gt" => lower = Bound::Excluded(value.clone()),
+                "$gte" => lower = Bound::Included(value.clone()),
+                "$lt" => upper = Bound::Excluded(value.clone()),
+                "$lte" => upper = Bound::Included(value.clone()),
+                _ => {}
+            }
+        }
+
+        if matches!(lower, Bound::Unbounded) && matches!(upper, Bound::Unbounded) {
+            return None;
+        }
+
+        return Some(IndexBounds::Range(lower, upper));
+    }
+
+    /// Returns the sorted positions of the documents whose first indexed field
+    /// is inside the bounds. The documents still have to be checked against the query.
+    pub fn positions_in(&self, bounds: &IndexBounds) -> Vec<usize> {
+        let mut positions: Vec<usize> = Vec::new();
+        match bounds {
+            IndexBounds::Points(values) => {
+                for value in values.iter() {
+                    let lower = Bound::Included(self.prefix_key(value, Bson::MinKey));
+                    let upper = Bound::Included(self.prefix_key(value, Bson::MaxKey));
+                    self.collect_range(lower, upper, &mut positions);
+                }
+            }
+            IndexBounds::Range(lower, upper) => {
+                let lower = match lower {
+                    Bound::Included(value) => Bound::Included(self.prefix_key(value, Bson::MinKey)),
+                    Bound::Excluded(value) => Bound::Excluded(self.prefix_key(value, Bson::MaxKey)),
+                    Bound::Unbounded => Bound::Unbounded,
+                };
+                let upper = match upper {
+                    Bound::Included(value) => Bound::Included(self.prefix_key(value, Bson::MaxKey)),
+                    Bound::Excluded(value) => Bound::Excluded(self.prefix_key(value, Bson::MinKey)),
+                    Bound::Unbounded => Bound::Unbounded,
+                };
+                self.collect_range(lower, upper, &mut positions);
+            }
+        }
+
+        positions.sort_unstable();
+        positions.dedup();
+        return positions;
+    }
+
+    /// Builds a key with the value as first field, filling the rest of a
+    /// compound index with MinKey or MaxKey to reach the edges of the prefix.
+    fn prefix_key(&self, value: &Bson, fill: Bson) -> IndexKey {
+        let mut key = vec![value.clone()];
+        while key.len() < self.keys.len() {
+            key.push(fill.clone());
+        }
+
+        return IndexKey(key);
+    }
+
+    fn collect_range(
+        &self,
+        lower: Bound<IndexKey>,
+        upper: Bound<IndexKey>,
+        positions: &mut Vec<usize>,
+    ) {
+        // BTreeMap::range panics on reversed ranges, so empty ones are skipped
+        let empty = match (&lower, &upper) {
+            (Bound::Included(l), Bound::Included(u)) => l > u,
+            (Bound::Included(l) | Bound::Excluded(l), Bound::Included(u) | Bound::Excluded(u)) => {
+                l >= u
+            }
+            _ => false,
+        };
+        if empty {
+            return;
+        }
+
+        for (_key, items) in self.entries.range((lower, upper)) {
+            positions.extend(items.iter());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, Document};
+
+    use super::{index_name, validate_index_keys, validate_index_options, Index};
+
+    fn index_on(keys: Document, documents: &[Document]) -> Index {
+        let name = index_name(&keys);
+        let mut index = Index::new(name, keys, &Document::new());
+        index.rebuild(documents);
+        return index;
+    }
+
+    fn positions(index: &Index, query: Document) -> Option<Vec<usize>> {
+        let bounds = index.bounds_for(&query)?;
+        return Some(index.positions_in(&bounds));
+    }
+
+    #[test]
+    fn names_and_validates_keys() {
+        assert_eq!(index_name(&doc! { "age": 1, "name": -1 }), "age_1_name_-1");
+        assert_eq!(index_name(&doc! { "title": "text" }), "title_text");
+        assert!(validate_index_keys(&doc! { "age": -1.0 }).is_ok());
+        assert!(validate_index_keys(&doc! { "age": 2 }).is_err());
+        assert!(validate_index_keys(&Document::new()).is_err());
+    }
+
+    #[test]
+    fn reads_points_and_ranges() {
+        let documents = vec![
+            doc! { "age": 30 },
+            doc! { "age": 18 },
+            doc! { "age": 25.5 },
+            doc! { "name": "none" },
+            doc! { "age": 40_i64 },
+        ];
+        let index = index_on(doc! { "age": 1 }, &documents);
+
+        assert_eq!(positions(&index, doc! { "age": 30.0 }), Some(vec![0]));
+        assert_eq!(
+            positions(&index, doc! { "age": { "$in": [18, 40] } }),
+            Some(vec![1, 4])
+        );
+        assert_eq!(
+            positions(&index, doc! { "age": { "$gt": 18, "$lte": 30 } }),
+            Some(vec![0, 2])
+        );
+        assert_eq!(
+            positions(&index, doc! { "age": { "$gt": 40 } }),
+            Some(vec![])
+        );
+        assert_eq!(
+            positions(&index, doc! { "age": { "$gt": 40, "$lt": 18 } }),
+            Some(vec![])
+        );
+        // Missing fields are indexed as null
+        assert_eq!(positions(&index, doc! { "age": null }), Some(vec![3]));
+        assert_eq!(positions(&index, doc! { "age": { "$ne": 30 } }), None);
+        assert_eq!(positions(&index, doc! { "name": "none" }), None);
+    }
+
+    #[test]
+    fn uses_the_first_field_of_compound_indexes() {
+        let documents = vec![
+            doc! { "a": 1, "b": "x" },
+            doc! { "a": 2, "b": "y" },
+            doc! { "a": 1, "b": "z" },
+        ];
+        let index = index_on(doc! { "a": 1, "b": -1 }, &documents);
+
+        assert_eq!(positions(&index, doc! { "a": 1 }), Some(vec![0, 2]));
+        assert_eq!(
+            positions(&index, doc! { "a": { "$gte": 2 } }),
+            Some(vec![1])
+        );
+        assert_eq!(positions(&index, doc! { "b": "x" }), None);
+    }
+
+    #[test]
+    fn indexes_each_array_element() {
+        let documents = vec![
+            doc! { "tags": ["red", "blue"] },
+            doc! { "tags": "blue" },
+            doc! { "tags": [] },
+        ];
+        let index = index_on(doc! { "tags": 1 }, &documents);
+
+        assert_eq!(positions(&index, doc! { "tags": "blue" }), Some(vec![0, 1]));
+        assert_eq!(
+            positions(&index, doc! { "tags": ["red", "blue"] }),
+            Some(vec![0])
+        );
+    }
+
+    #[test]
+    fn keeps_one_bound_on_multikey_indexes() {
+        // [1, 10] matches both conditions with different elements
+        let documents = vec![doc! { "n": [1, 10] }, doc! { "n": 5 }];
+        let index = index_on(doc! { "n": 1 }, &documents);
+
+        let found = positions(&index, doc! { "n": { "$gt": 4, "$lt": 6 } }).unwrap();
+        assert!(found.contains(&0));
+        assert!(found.contains(&1));
+
+        let index = index_on(doc! { "n": 1 }, &[doc! { "n": 1 }, doc! { "n": 10 }]);
+        assert_eq!(
+            positions(&index, doc! { "n": { "$gt": 4, "$lt": 6 } }),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn forgets_removed_documents() {
+        let documents = vec![doc! { "a": 1 }, doc! { "a": 1 }];
+        let mut index = index_on(doc! { "a": 1 }, &documents);
+
+        index.remove_document(&documents[0], 0);
+        assert_eq!(positions(&index, doc! { "a": 1 }), Some(vec![1]));
+        index.add_document(&doc! { "a": [1, 2] }, 2);
+        assert_eq!(positions(&index, doc! { "a": 1 }), Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn validates_options() {
+        assert!(validate_index_options(&doc! { "unique": true, "sparse": false }).is_ok());
+        assert!(validate_index_options(&doc! { "unique": 1 }).is_err());
+        assert!(validate_index_options(&doc! { "background": true }).is_err());
+        let partial = doc! { "partialFilterExpression": { "a": { "$gt": 1 } } };
+        assert!(validate_index_options(&partial).is_ok());
+        assert!(validate_index_options(&doc! { "partialFilterExpression": 1 }).is_err());
+        let invalid = doc! { "partialFilterExpression": { "a": { "$bad": 1 } } };
+        assert!(validate_index_options(&invalid).is_err());
+        let both = doc! { "sparse": true, "partialFilterExpression": { "a": 1 } };
+        assert!(validate_index_options(&both).is_err());
+    }
+
+    #[test]
+    fn sparse_indexes_skip_null_queries() {
+        let options = doc! { "sparse": true };
+        let mut index = Index::new("a_1".to_string(), doc! { "a": 1 }, &options);
+        index.rebuild(&[doc! { "a": 1 }, doc! { "b": 1 }]);
+
+        assert_eq!(positions(&index, doc! { "a": 1 }), Some(vec![0]));
+        assert_eq!(positions(&index, doc! { "a": null }), None);
+        assert_eq!(positions(&index, doc! { "a": { "$in": [1, null] } }), None);
+    }
+
+    #[test]
+    fn partial_indexes_need_their_filter_in_the_query() {
+        let options = doc! { "partialFilterExpression": { "active": true } };
+        let mut index = Index::new("a_1".to_string(), doc! { "a": 1 }, &options);
+        let documents = vec![
+            doc! { "a": 1, "active": true },
+            doc! { "a": 1, "active": false },
+        ];
+        index.rebuild(&documents);
+
+        assert_eq!(positions(&index, doc! { "a": 1 }), None);
+        assert_eq!(
+            positions(&index, doc! { "a": 1, "active": true }),
+            Some(vec![0])
+        );
+    }
+}
@@ -0,0 +1,357 @@
+use std::{iter::Peekable, str::CharIndices};
+
+/// Why a statement cant be split into tokens, or a json token cant be parsed,
+/// and the line and column (counted in characters from 1) where the problem is.
+pub struct LexError {
+    line: usize,
+    column: usize,
+    message: String,
+    unterminated: bool,
+}
+
+impl LexError {
+    fn new((line, column): (usize, usize), message: String) -> Self {
+        Self {
+            line,
+            column,
+            message,
+            unterminated: false,
+        }
+    }
+
+    /// An error for a bracket, string or regex that is still open at the end
+    /// of the input, which more lines could complete.
+    fn unterminated(position: (usize, usize), message: String) -> Self {
+        let mut err = Self::new(position, message);
+        err.unterminated = true;
+        return err;
+    }
+
+    pub fn get_line(&self) -> usize {
+        return self.line;
+    }
+
+    pub fn get_column(&self) -> usize {
+        return self.column;
+    }
+
+    pub fn get_message(&self) -> String {
+        return self.message.to_owned();
+    }
+}
+
+/// A token of a statement and the line and column where it starts. Quoted
+/// strings are unquoted, while words and json values keep their text.
+pub struct Token {
+    text: String,
+    json: bool,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    pub fn get_text(&self) -> &str {
+        return &self.text;
+    }
+
+    pub fn is_json(&self) -> bool {
+        return self.json;
+    }
+
+    /// An error at the byte `offset` of the text of the token, such as the place
+    /// where its json cant be parsed.
+    pub fn error_at(&self, offset: usize, message: String) -> LexError {
+        let (mut line, mut column) = (self.line, self.column);
+        for c in self.text[..offset.min(self.text.len())].chars() {
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+
+        return LexError::new((line, column), message);
+    }
+}
+
+/// Reads the characters of the input keeping the line and column of each one.
+struct Cursor<'a> {
+    chars: Peekable<CharIndices<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn position(&self) -> (usize, usize) {
+        return (self.line, self.column);
+    }
+
+    fn peek(&mut self) -> Option<(usize, char)> {
+        return self.chars.peek().copied();
+    }
+
+    fn advance(&mut self) -> Option<(usize, char)> {
+        let next = self.chars.next();
+        match next {
+            Some((_i, '\n')) => {
+                self.line += 1;
+                self.column = 1;
+            }
+            Some(_) => self.column += 1,
+            None => {}
+        }
+        return next;
+    }
+}
+
+/// Splits a statement into its tokens: words such as keywords or collection
+/// names, "quoted strings" and whole json values. Words end at whitespace or
+/// where a json value starts, so `find users{"age": 3}` has three tokens. Json
+/// values end at the bracket that closes them, and the strings or `/regex/`
+/// literals inside them keep their whitespace and brackets.
+pub fn lex_statement(input: &str) -> Result<Vec<Token>, LexError> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut cursor = Cursor {
+        chars: input.char_indices().peekable(),
+        line: 1,
+        column: 1,
+    };
+
+    while let Some((start, c)) = cursor.peek() {
+        let position = cursor.position();
+        if c.is_whitespace() {
+            cursor.advance();
+            continue;
+        }
+
+        let end = match c {
+            '{' | '[' => read_json_value(&mut cursor)?,
+            '"' => {
+                cursor.advance();
+                let end = read_string(&mut cursor, position)?;
+                match serde_json::from_str::<String>(&input[start..end]) {
+                    Ok(text) => tokens.push(Token {
+                        text,
+                        json: false,
+                        line: position.0,
+                        column: position.1,
+                    }),
+                    Err(_e) => return Err(LexError::new(position, "Invalid string".to_string())),
+                }
+                continue;
+            }
+            '}' | ']' => return Err(LexError::new(position, format!("Unexpected '{}'", c))),
+            _ => {
+                let mut end = input.len();
+                while let Some((i, c)) = cursor.peek() {
+                    if c.is_whitespace() || c == '{' || c == '[' {
+                        end = i;
+                        break;
+                    }
+                    cursor.advance();
+                }
+                end
+            }
+        };
+        tokens.push(Token {
+            text: input[start..end].to_string(),
+            json: c == '{' || c == '[',
+            line: position.0,
+            column: position.1,
+        });
+    }
+
+    return Ok(tokens);
+}
+
+/// Reads a string whose opening quote was already read, up to its closing
+/// quote. Returns the byte offset after the closing quote.
+fn read_string(cursor: &mut Cursor, position: (usize, usize)) -> Result<usize, LexError> {
+    let mut escaped = false;
+    while let Some((i, c)) = cursor.advance() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            return Ok(i + 1);
+        }
+    }
+
+    return Err(LexError::unterminated(
+        position,
+        "Unterminated string".to_string(),
+    ));
+}
+
+/// Reads a json object or array up to the bracket that closes it. Returns the
+/// byte offset after that bracket.
+fn read_json_value(cursor: &mut Cursor) -> Result<usize, LexError> {
+    // The brackets still open, with the position of each one
+    let mut open: Vec<(char, (usize, usize))> = Vec::new();
+
+    loop {
+        let position = cursor.position();
+        let (i, c) = match cursor.advance() {
+            Some(next) => next,
+            None => break,
+        };
+        match c {
+            '{' | '[' => open.push((c, position)),
+            '}' | ']' => {
+                let expected = match open.pop() {
+                    Some(('{', _position)) => '}',
+                    _ => ']',
+                };
+                if c != expected {
+                    let message = format!("Expected '{}' but found '{}'", expected, c);
+                    return Err(LexError::new(position, message));
+                }
+                if open.is_empty() {
+                    return Ok(i + 1);
+                }
+            }
+            '"' => {
+                read_string(cursor, position)?;
+            }
+            '/' => read_regex(cursor, position)?,
+            _ => {}
+        }
+    }
+
+    let (bracket, position) = open[open.len() - 1];
+    return Err(LexError::unterminated(
+        position,
+        format!("Unclosed '{}'", bracket),
+    ));
+}
+
+/// Reads a `/pattern/` literal whose opening slash was already read.
+fn read_regex(cursor: &mut Cursor, position: (usize, usize)) -> Result<(), LexError> {
+    let mut escaped = false;
+    while let Some((_i, c)) = cursor.advance() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '/' {
+            return Ok(());
+        }
+    }
+
+    return Err(LexError::unterminated(
+        position,
+        "Unterminated regular expression".to_string(),
+    ));
+}
+
+/// Returns true unless the statement still has an open bracket, string or
+/// regex. A statement ending in `;` is complete either way.
+pub fn statement_is_complete(input: &str) -> bool {
+    let input = input.trim_end();
+    if input.ends_with(';') {
+        return true;
+    }
+
+    match lex_statement(input) {
+        Err(err) => return !err.unterminated,
+        Ok(_tokens) => return true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lex_statement, statement_is_complete, LexError, Token};
+
+    fn texts(input: &str) -> Vec<String> {
+        let tokens = lex_statement(input).ok().unwrap();
+        return tokens
+            .iter()
+            .map(|token| token.get_text().to_string())
+            .collect();
+    }
+
+    fn error(input: &str) -> LexError {
+        match lex_statement(input) {
+            Ok(tokens) => panic!("{} tokens in {}", tokens.len(), input),
+            Err(err) => return err,
+        }
+    }
+
+    fn location(err: &LexError) -> (usize, usize, String) {
+        return (err.get_line(), err.get_column(), err.get_message());
+    }
+
+    #[test]
+    fn splits_words_and_json_values() {
+        assert_eq!(
+            texts("find users{\"age\": 3}  [1, 2]"),
+            vec!["find", "users", "{\"age\": 3}", "[1, 2]"]
+        );
+        assert_eq!(
+            texts("insert c {\"a\": \"{ not ] closed\", \"r\": /a}b/}"),
+            vec!["insert", "c", "{\"a\": \"{ not ] closed\", \"r\": /a}b/}"]
+        );
+        assert_eq!(texts("   "), Vec::<String>::new());
+    }
+
+    #[test]
+    fn unquotes_strings() {
+        let tokens: Vec<Token> = lex_statement("distinct c \"full name\" {}").ok().unwrap();
+        assert_eq!(tokens[2].get_text(), "full name");
+        assert!(!tokens[2].is_json());
+        assert!(tokens[3].is_json());
+        assert_eq!(texts("a \"say \\\"hi\\\"\""), vec!["a", "say \"hi\""]);
+    }
+
+    #[test]
+    fn locates_errors() {
+        let expected = (1, 8, "Unclosed '{'".to_string());
+        assert_eq!(location(&error("find c {\"a\": {\"b\": 1}")), expected);
+        let expected = (1, 9, "Expected '}' but found ']'".to_string());
+        assert_eq!(location(&error("find c {]")), expected);
+        let expected = (1, 8, "Unterminated string".to_string());
+        assert_eq!(location(&error("find c \"abc")), expected);
+        let expected = (1, 8, "Unexpected '}'".to_string());
+        assert_eq!(location(&error("find c }")), expected);
+        let expected = (2, 8, "Unterminated regular expression".to_string());
+        assert_eq!(location(&error("find c {\n  \"a\": /ab}")), expected);
+    }
+
+    #[test]
+    fn counts_columns_in_characters() {
+        let expected = (1, 7, "Unclosed '['".to_string());
+        assert_eq!(location(&error("ä ü é [")), expected);
+    }
+
+    #[test]
+    fn locates_errors_inside_tokens() {
+        let tokens = lex_statement("find c\n  {\"a\":\n   tru}").ok().unwrap();
+        let json = &tokens[2];
+        let offset = json.get_text().find("tru").unwrap();
+        let err = json.error_at(offset, "Expected value".to_string());
+        assert_eq!(location(&err), (3, 4, "Expected value".to_string()));
+        let err = json.error_at(1000, "End".to_string());
+        assert_eq!((err.get_line(), err.get_column()), (3, 8));
+    }
+
+    #[test]
+    fn waits_for_open_brackets_strings_and_regexes() {
+        assert!(statement_is_complete("find users"));
+        assert!(statement_is_complete("insert c {\"a\": 1}"));
+        assert!(!statement_is_complete("insert c {\"a\":"));
+        assert!(!statement_is_complete("insert c {\"a\": [1,\n 2"));
+        assert!(!statement_is_complete("find c {\"a\": \"x"));
+        assert!(!statement_is_complete("find c {\"a\": /x"));
+        assert!(statement_is_complete("find c {\"a\": \"{[\"}"));
+    }
+
+    #[test]
+    fn completes_on_semicolons_and_invalid_input() {
+        assert!(statement_is_complete("insert c {\"a\": ;  "));
+        // More lines cant fix a bracket closed by the wrong one
+        assert!(statement_is_complete("find c {]"));
+        assert!(statement_is_complete("find c }"));
+    }
+}
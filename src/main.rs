@@ -1,37 +1,80 @@
+#![allow(clippy::needless_return, clippy::enum_variant_names)]
+
 use bson::from_reader;
 use collection::CollectionResult;
+use commands::handle_command;
 use database::Database;
-use rustyline::{error::ReadlineError, history::FileHistory, DefaultEditor, Editor};
+use helper::ReplHelper;
+use lexer::{lex_statement, LexError};
+use rustyline::{error::ReadlineError, history::FileHistory, Config, Editor};
+use settings::Settings;
 use statement::{execute_statement, prepare_statement, ExecuteResult, PrepareResult, Statement};
 use std::{
     env,
     fs::File,
     io::{self, Read, Write},
+    path::Path,
+    process,
+    time::Instant,
 };
 
+mod aggregation;
 mod bson_functions;
 mod queries;
 mod statement;
 mod database;
 mod collection;
+mod commands;
+mod filter;
+mod helper;
+mod history;
+mod index;
+mod lexer;
+mod ordering;
+mod output;
+mod planner;
+mod projection;
+mod settings;
+mod text;
+mod update;
 
 const TABLE_MAX_DOCUMENTS: usize = 10000;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let filename;
-    if args.len() > 1 {
-        filename = args[1].as_str();
+    let filename = if args.len() > 1 {
+        args[1].as_str()
     } else {
-        filename = "./db.docl";
-    }
+        "./db.docl"
+    };
 
-    let mut database: Database = db_open(filename);
-    let mut rl = DefaultEditor::new().unwrap();
+    let mut database: Database = match db_open(filename) {
+        Ok(database) => database,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    let mut settings = Settings::new();
+    let mut rl: Editor<ReplHelper, FileHistory> =
+        Editor::with_config(editor_config(&settings)).unwrap();
+    rl.set_helper(Some(ReplHelper::new()));
+    if let Some(path) = settings.get_history().get_path() {
+        // The file doesnt exist until the first session ends
+        let _ = rl.load_history(path);
+    }
     loop {
-        print_prompt();
-        let exit = get_input(&mut rl, &mut database);
+        if let Some(helper) = rl.helper_mut() {
+            helper.refresh(&database);
+        }
+        print_prompt("db > ");
+        let exit = get_input(&mut rl, &mut database, &mut settings);
         if exit {
+            if let Some(path) = settings.get_history().get_path() {
+                if let Err(err) = rl.save_history(path) {
+                    eprintln!("Couldnt save the history: {}", err);
+                }
+            }
             match commit_changes(&mut database) {
                 Ok(ok) => println!("{}", ok),
                 Err(err) => println!("{}", err),
@@ -41,64 +84,49 @@ fn main() {
     }
 }
 
-fn print_prompt() {
-    print!("db > ");
+/// Lines starting with a space, like repeated lines, are left out of the history.
+fn editor_config(settings: &Settings) -> Config {
+    let builder = Config::builder().history_ignore_space(true);
+    let max_size = settings.get_history().get_max_size();
+    match builder.clone().max_history_size(max_size) {
+        Ok(builder) => return builder.build(),
+        Err(err) => {
+            eprintln!("History size {} refused ({}), using the default size", max_size, err);
+            // Keeps the settings above with the default history size
+            return builder.build();
+        }
+    }
+}
+
+fn print_prompt(prompt: &str) {
+    print!("{}", prompt);
     io::stdout().flush().expect("Failed to flush stdout");
 }
 
-fn get_input(rl: &mut Editor<(), FileHistory>, database: &mut Database) -> bool {
+fn get_input(
+    rl: &mut Editor<ReplHelper, FileHistory>,
+    database: &mut Database,
+    settings: &mut Settings,
+) -> bool {
     let readline = rl.readline("db> ");
     match readline {
         Ok(line) => {
-            let str = line.trim();
-
-            if let Some(command) = str.chars().nth(0) {
-                if command == '.' {
-                    return handle_command(str);
-                } else {
-                    let mut statement = Statement::new();
-
-                    let prepare = prepare_statement(str, &mut statement, database);
-                    match prepare {
-                        PrepareResult::PrepareSuccess => {
-                            match execute_statement(statement, database) {
-                                ExecuteResult::ExecuteSuccess => println!("Executed."),
-                                ExecuteResult::ExecuteFailed => println!("Failed."),
-                                ExecuteResult::ExecuteTableFull => println!("Table full."),
-                                ExecuteResult::ExecuteTableUndefined => {
-                                    println!("Collection doesnt exist.")
-                                }
-                                ExecuteResult::ExecuteCollectionAlreadyExists => {
-                                    eprintln!("Collection already exists.")
-                                }
-                                ExecuteResult::ExecuteCantSaveDatabase => {
-                                    println!("Cant commit changes to database")
-                                }
-                            }
-                        }
-                        PrepareResult::PrepareUnrecognizedStatement => {
-                            eprintln!("Unrecognized keyword at start of '{}'", str);
-                        }
-                        PrepareResult::PrepareSyntaxError => {
-                            eprintln!("Syntax error. Could not parse statement");
-                        }
-                        PrepareResult::PrepareCollectionDoesntExist => {
-                            eprintln!("Collection doesnt exist")
-                        }
-                        PrepareResult::PrepareMissingCollection => {
-                            eprintln!("Collection is missing in query.")
-                        }
-                        PrepareResult::PrepareCantParseJson => {
-                            eprintln!("The JSON cant be parsed");
-                        }
-                    }
-                    return false;
-                }
+            if !line.trim().is_empty() && settings.get_history().should_save(&line) {
+                let _ = rl.add_history_entry(line.as_str());
             }
+
+            return run_input(&line, database, settings);
         }
         Err(ReadlineError::Interrupted) => {
             return true;
         }
+        Err(ReadlineError::Eof) => {
+            // The input ended while the helper was waiting for the rest of a statement
+            if let Some(unfinished) = rl.helper().and_then(|helper| helper.take_unfinished()) {
+                report_unterminated(&unfinished);
+            }
+            return true;
+        }
         Err(error) => {
             eprintln!("Error reading input: {}", error);
         }
@@ -107,21 +135,128 @@ fn get_input(rl: &mut Editor<(), FileHistory>, database: &mut Database) -> bool
     return false;
 }
 
-fn handle_command(command: &str) -> bool {
-    let command_parsed: Vec<&str> = command.split(' ').collect();
-    let input_command = command_parsed[0];
-    match input_command {
-        ".exit" => {
-            println!("Bye!");
-            return true;
-        }
-        _ => {
-            println!("Command '{}' not recognized", command);
+/// Runs a dot command or a statement. Returns true when the REPL has to exit.
+fn run_input(input: &str, database: &mut Database, settings: &mut Settings) -> bool {
+    let str = input.trim();
+    let str = str.strip_suffix(';').unwrap_or(str).trim_end();
+
+    if let Some(command) = str.chars().next() {
+        if command == '.' {
+            return handle_command(str, database, settings);
+        } else {
+            let mut statement = Statement::new();
+
+            let prepare = prepare_statement(str, &mut statement, database);
+            match prepare {
+                PrepareResult::PrepareSuccess => {
+                    let start = Instant::now();
+                    match execute_statement(statement, database, settings) {
+                        ExecuteResult::ExecuteSuccess => println!("Executed."),
+                        ExecuteResult::ExecuteFailed => println!("Failed."),
+                        ExecuteResult::ExecuteTableFull => println!("Table full."),
+                        ExecuteResult::ExecuteTableUndefined => {
+                            println!("Collection doesnt exist.")
+                        }
+                        ExecuteResult::ExecuteCollectionAlreadyExists => {
+                            eprintln!("Collection already exists.")
+                        }
+                        ExecuteResult::ExecuteCantSaveDatabase => {
+                            println!("Cant commit changes to database")
+                        }
+                        ExecuteResult::ExecuteUpdateFailed(err) => {
+                            eprintln!("Update failed: {}", err)
+                        }
+                        ExecuteResult::ExecuteDuplicateId(id) => {
+                            eprintln!("A document with _id {} already exists.", id)
+                        }
+                        ExecuteResult::ExecuteIndexFailed(err) => {
+                            eprintln!("Index failed: {}", err)
+                        }
+                        ExecuteResult::ExecuteDuplicateKey(key) => {
+                            eprintln!("Duplicate key {}.", key)
+                        }
+                        ExecuteResult::ExecutePipelineFailed(err) => {
+                            eprintln!("Pipeline failed: {}", err)
+                        }
+                    }
+                    if settings.get_timer() {
+                        let millis = start.elapsed().as_secs_f64() * 1000.0;
+                        println!("Run Time: {:.3} ms", millis);
+                    }
+                }
+                PrepareResult::PrepareUnrecognizedStatement => {
+                    eprintln!("Unrecognized keyword at start of '{}'", str);
+                }
+                PrepareResult::PrepareSyntaxError => {
+                    eprintln!("Syntax error. Could not parse statement");
+                }
+                PrepareResult::PrepareCollectionDoesntExist => {
+                    eprintln!("Collection doesnt exist")
+                }
+                PrepareResult::PrepareMissingCollection => {
+                    eprintln!("Collection is missing in query.")
+                }
+                PrepareResult::PrepareCantParseJson(err) => {
+                    print_located_error(str, "The JSON cant be parsed", &err);
+                }
+                PrepareResult::PrepareInvalidFilter(err) => {
+                    eprintln!("Invalid filter: {}", err);
+                }
+                PrepareResult::PrepareInvalidUpdate(err) => {
+                    eprintln!("Invalid update: {}", err);
+                }
+                PrepareResult::PrepareInvalidProjection(err) => {
+                    eprintln!("Invalid projection: {}", err);
+                }
+                PrepareResult::PrepareInvalidSort(err) => {
+                    eprintln!("Invalid sort: {}", err);
+                }
+                PrepareResult::PrepareInvalidPipeline(err) => {
+                    eprintln!("Invalid pipeline: {}", err);
+                }
+                PrepareResult::PrepareInvalidIndex(err) => {
+                    eprintln!("Invalid index: {}", err);
+                }
+                PrepareResult::PrepareParseError(err) => {
+                    print_located_error(str, "Syntax error", &err);
+                }
+            }
+            return false;
         }
     }
+
     return false;
 }
 
+/// Reports a statement the input ended in the middle of, with the bracket,
+/// string or regex it left open.
+fn report_unterminated(input: &str) {
+    match lex_statement(input) {
+        Err(err) => print_located_error(input, "Unterminated statement", &err),
+        Ok(_tokens) => eprintln!("Unterminated statement"),
+    }
+}
+
+/// Prints the error with its column, and its line when the input has several,
+/// followed by that line of the input and a caret under the column.
+fn print_located_error(input: &str, description: &str, err: &LexError) {
+    let column = err.get_column();
+    if input.contains('\n') {
+        eprintln!(
+            "{} at line {}, column {}: {}",
+            description,
+            err.get_line(),
+            column,
+            err.get_message()
+        );
+    } else {
+        eprintln!("{} at column {}: {}", description, column, err.get_message());
+    }
+    let source = input.lines().nth(err.get_line() - 1).unwrap_or("");
+    eprintln!("  {}", source.replace('\t', " "));
+    eprintln!("  {}^", " ".repeat(column - 1));
+}
+
 fn get_collection(
     statement: &mut Statement,
     database: &mut Database,
@@ -138,19 +273,25 @@ fn get_collection(
     return CollectionResult::CollectionDoesntExist;
 }
 
-fn db_open(filename: &str) -> Database {
+/// Opens the database file, or a new database when the file doesnt exist yet.
+fn db_open(filename: &str) -> Result<Database, String> {
     //const ARRAY_REPEAT_VALUE: Option<Row> = None;
-    match database_opener(filename) {
-        Ok(db) => return db,
-        Err(e) => {
-            eprintln!("{}", e);
-            return Database::new(filename.to_owned(), Vec::new());
-        }
+    if !Path::new(filename).exists() {
+        eprintln!("Database file doesnt exist");
+        return Ok(Database::new(filename.to_owned(), Vec::new()));
     }
+
+    return database_opener(filename);
 }
 
 fn commit_changes(database: &mut Database) -> Result<String, String> {
-    let document = bson::to_document(database).expect("Failed to serialize Database");
+    return save_database(database, &database.get_filename());
+}
+
+fn save_database(database: &Database, filename: &str) -> Result<String, String> {
+    let mut document = bson::to_document(database).expect("Failed to serialize Database");
+    // A copy opens as its own file
+    document.insert("filename", filename);
 
     let mut serialized_data: Vec<u8> = Vec::new();
     match document.to_writer(&mut serialized_data) {
@@ -158,7 +299,7 @@ fn commit_changes(database: &mut Database) -> Result<String, String> {
         Err(_err) => return Err("Failed to serialize BSON".to_string()),
     }
 
-    match File::create(&database.get_filename()) {
+    match File::create(filename) {
         Ok(mut file) => match file.write_all(&serialized_data) {
             Ok(_ok) => return Ok("Database saved.".to_string()),
             Err(_err) => return Err("Couldnt save database".to_string()),
@@ -175,10 +316,17 @@ fn database_opener(filename: &str) -> Result<Database, String> {
             let mut buffer = Vec::new();
             match file.read_to_end(&mut buffer) {
                 Ok(_usize) => {
-                    let document = from_reader(&buffer[..]).expect("Failed to deserialize BSON");
+                    let document = match from_reader(&buffer[..]) {
+                        Ok(document) => document,
+                        Err(_e) => return Err(format!("'{}' isnt a database file", filename)),
+                    };
 
-                    return Ok(bson::from_bson::<Database>(document)
-                        .expect("Failed to convert BSON to Database"));
+                    let mut database = match bson::from_bson::<Database>(document) {
+                        Ok(database) => database,
+                        Err(_e) => return Err(format!("'{}' isnt a database file", filename)),
+                    };
+                    database.rebuild_indexes();
+                    return Ok(database);
                 }
                 Err(_e) => return Err("Error reading database file".to_string()),
             }
@@ -0,0 +1,360 @@
+use std::cmp::Ordering;
+
+use bson::{Bson, Document};
+
+use crate::filter::{as_integer, as_number, get_path_values};
+
+/// Position of each BSON type in the sort order, following MongoDB:
+/// MinKey, Null, numbers, strings, documents, arrays, binary data, ObjectId,
+/// booleans, dates, timestamps, regular expressions, JavaScript code and MaxKey.
+/// Undefined ranks as null, symbols as strings, and Int32, Int64, Double and
+/// Decimal128 share one rank so they are compared by value.
+fn type_rank(value: &Bson) -> u8 {
+    match value {
+        Bson::MinKey => return 0,
+        Bson::Null | Bson::Undefined => return 1,
+        Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) | Bson::Decimal128(_) => return 2,
+        Bson::String(_) | Bson::Symbol(_) => return 3,
+        Bson::Document(_) => return 4,
+        Bson::Array(_) => return 5,
+        Bson::Binary(_) => return 6,
+        Bson::ObjectId(_) => return 7,
+        Bson::Boolean(_) => return 8,
+        Bson::DateTime(_) => return 9,
+        Bson::Timestamp(_) => return 10,
+        Bson::RegularExpression(_) => return 11,
+        Bson::JavaScriptCode(_) | Bson::JavaScriptCodeWithScope(_) | Bson::DbPointer(_) => {
+            return 12
+        }
+        Bson::MaxKey => return 13,
+    }
+}
+
+/// Returns true when both values are of the same kind, so range operators can compare them.
+pub fn same_type_bracket(a: &Bson, b: &Bson) -> bool {
+    return type_rank(a) == type_rank(b);
+}
+
+/// Compares any two values, first by the rank of their type and then by value.
+pub fn compare_bson(a: &Bson, b: &Bson) -> Ordering {
+    let rank = type_rank(a).cmp(&type_rank(b));
+    if rank != Ordering::Equal {
+        return rank;
+    }
+
+    match (a, b) {
+        (Bson::String(a), Bson::String(b)) => return a.cmp(b),
+        (Bson::Symbol(a), Bson::Symbol(b)) => return a.cmp(b),
+        (Bson::String(a), Bson::Symbol(b)) | (Bson::Symbol(a), Bson::String(b)) => return a.cmp(b),
+        (Bson::Document(a), Bson::Document(b)) => return compare_documents(a, b),
+        (Bson::Array(a), Bson::Array(b)) => return compare_arrays(a, b),
+        (Bson::Binary(a), Bson::Binary(b)) => {
+            return a
+                .bytes
+                .len()
+                .cmp(&b.bytes.len())
+                .then(u8::from(a.subtype).cmp(&u8::from(b.subtype)))
+                .then(a.bytes.cmp(&b.bytes))
+        }
+        (Bson::ObjectId(a), Bson::ObjectId(b)) => return a.bytes().cmp(&b.bytes()),
+        (Bson::Boolean(a), Bson::Boolean(b)) => return a.cmp(b),
+        (Bson::DateTime(a), Bson::DateTime(b)) => return a.cmp(b),
+        (Bson::Timestamp(a), Bson::Timestamp(b)) => {
+            return (a.time, a.increment).cmp(&(b.time, b.increment))
+        }
+        (Bson::RegularExpression(a), Bson::RegularExpression(b)) => {
+            return a.pattern.cmp(&b.pattern).then(a.options.cmp(&b.options))
+        }
+        _ => {}
+    }
+
+    match type_rank(a) {
+        2 => return compare_numbers(a, b),
+        // JavaScript code and database pointers only need a stable order
+        12 => return format!("{:?}", a).cmp(&format!("{:?}", b)),
+        // MinKey, null and MaxKey have a single value
+        _ => return Ordering::Equal,
+    }
+}
+
+/// Returns true when both values are equal for queries, so the Int32 1, the
+/// Int64 1 and the Double 1.0 are the same value, also inside arrays and documents.
+pub fn values_equal(a: &Bson, b: &Bson) -> bool {
+    return compare_bson(a, b) == Ordering::Equal;
+}
+
+/// Compares numbers of any type by their value. Integers are compared exactly,
+/// also against doubles, 0.0 equals -0.0 and NaN is lower than every other
+/// number but equal to itself. Decimal128 values are compared as doubles.
+fn compare_numbers(a: &Bson, b: &Bson) -> Ordering {
+    match (as_integer(a), as_integer(b)) {
+        (Some(a), Some(b)) => return a.cmp(&b),
+        (Some(a), None) => return compare_integer_to_float(a, float_value(b)),
+        (None, Some(b)) => return compare_integer_to_float(b, float_value(a)).reverse(),
+        (None, None) => return compare_floats(float_value(a), float_value(b)),
+    }
+}
+
+fn float_value(value: &Bson) -> f64 {
+    match value {
+        Bson::Decimal128(decimal) => return decimal.to_string().parse().unwrap_or(f64::NAN),
+        _ => return as_number(value).unwrap_or(f64::NAN),
+    }
+}
+
+fn compare_floats(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => return Ordering::Equal,
+        (true, false) => return Ordering::Less,
+        (false, true) => return Ordering::Greater,
+        (false, false) => return a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+    }
+}
+
+/// Compares without converting the integer to a double, which would round
+/// integers above 2^53.
+fn compare_integer_to_float(integer: i64, float: f64) -> Ordering {
+    // i64 covers [-2^63, 2^63)
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+    if float.is_nan() {
+        return Ordering::Greater;
+    }
+    if float >= LIMIT {
+        return Ordering::Less;
+    }
+    if float < -LIMIT {
+        return Ordering::Greater;
+    }
+
+    let whole = float.trunc();
+    match integer.cmp(&(whole as i64)) {
+        Ordering::Equal => return compare_floats(whole, float),
+        ordering => return ordering,
+    }
+}
+
+fn compare_documents(a: &Document, b: &Document) -> Ordering {
+    let mut a_iter = a.iter();
+    let mut b_iter = b.iter();
+    loop {
+        match (a_iter.next(), b_iter.next()) {
+            (Some((a_key, a_value)), Some((b_key, b_value))) => {
+                let ordering = type_rank(a_value)
+                    .cmp(&type_rank(b_value))
+                    .then(a_key.cmp(b_key))
+                    .then(compare_bson(a_value, b_value));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(_), None) => return Ordering::Greater,
+            (None, Some(_)) => return Ordering::Less,
+            (None, None) => return Ordering::Equal,
+        }
+    }
+}
+
+fn compare_arrays(a: &[Bson], b: &[Bson]) -> Ordering {
+    for (a_value, b_value) in a.iter().zip(b.iter()) {
+        let ordering = compare_bson(a_value, b_value);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    return a.len().cmp(&b.len());
+}
+
+/// Checks that every key of the sort specification is 1 (ascending) or -1 (descending).
+pub fn validate_sort(sort: &Document) -> Result<(), String> {
+    if sort.is_empty() {
+        return Err("The sort specification is empty".to_string());
+    }
+
+    for (key, direction) in sort.iter() {
+        match as_number(direction) {
+            Some(direction) if direction == 1.0 || direction == -1.0 => {}
+            _ => return Err(format!("The sort direction of '{}' must be 1 or -1", key)),
+        }
+    }
+
+    return Ok(());
+}
+
+/// Sorts the documents by every key of the sort specification in order. Arrays
+/// sort by their lowest element when ascending and their highest when descending.
+pub fn sort_documents(documents: &mut [Document], sort: &Document) {
+    documents.sort_by(|a, b| compare_by_sort(a, b, sort));
+}
+
+pub fn compare_by_sort(a: &Document, b: &Document, sort: &Document) -> Ordering {
+    for (key, direction) in sort.iter() {
+        let descending = as_number(direction).unwrap_or(1.0) < 0.0;
+        let a_key = sort_key(a, key, descending);
+        let b_key = sort_key(b, key, descending);
+        let mut ordering = compare_bson(&a_key, &b_key);
+        if descending {
+            ordering = ordering.reverse();
+        }
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    return Ordering::Equal;
+}
+
+fn sort_key(doc: &Document, path: &str, descending: bool) -> Bson {
+    let mut candidates: Vec<&Bson> = Vec::new();
+    for value in get_path_values(doc, path) {
+        match value {
+            Bson::Array(items) => candidates.extend(items.iter()),
+            _ => candidates.push(value),
+        }
+    }
+
+    let selected = if descending {
+        candidates.into_iter().max_by(|a, b| compare_bson(a, b))
+    } else {
+        candidates.into_iter().min_by(|a, b| compare_bson(a, b))
+    };
+
+    return selected.cloned().unwrap_or(Bson::Null);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use bson::{doc, oid::ObjectId, Bson, DateTime, Document};
+
+    use super::{compare_bson, sort_documents, validate_sort, values_equal};
+
+    fn sorted_ids(documents: Vec<Document>, sort: Document) -> Vec<i32> {
+        let mut documents = documents;
+        sort_documents(&mut documents, &sort);
+        return documents
+            .iter()
+            .map(|doc| doc.get_i32("_id").unwrap())
+            .collect();
+    }
+
+    #[test]
+    fn orders_mixed_types() {
+        let ordered = vec![
+            Bson::MinKey,
+            Bson::Null,
+            Bson::Int32(5),
+            Bson::String("a".to_string()),
+            Bson::Document(doc! { "a": 1 }),
+            Bson::Array(vec![Bson::Int32(1)]),
+            Bson::ObjectId(ObjectId::new()),
+            Bson::Boolean(false),
+            Bson::DateTime(DateTime::from_millis(0)),
+            Bson::MaxKey,
+        ];
+        for pair in ordered.windows(2) {
+            assert_eq!(compare_bson(&pair[0], &pair[1]), Ordering::Less);
+            assert_eq!(compare_bson(&pair[1], &pair[0]), Ordering::Greater);
+        }
+        assert_eq!(compare_bson(&Bson::Undefined, &Bson::Null), Ordering::Equal);
+    }
+
+    #[test]
+    fn orders_values_of_the_same_type() {
+        let less = |a: Bson, b: Bson| compare_bson(&a, &b) == Ordering::Less;
+        assert!(less(Bson::from("abc"), Bson::from("abd")));
+        assert!(less(Bson::from(false), Bson::from(true)));
+        assert!(less(Bson::from(vec![1, 2]), Bson::from(vec![1, 3])));
+        assert!(less(Bson::from(vec![1]), Bson::from(vec![1, 0])));
+        assert!(less(
+            Bson::from(doc! { "a": 1 }),
+            Bson::from(doc! { "a": 1, "b": 0 })
+        ));
+        assert!(less(
+            Bson::from(doc! { "a": 9 }),
+            Bson::from(doc! { "b": 0 })
+        ));
+    }
+
+    #[test]
+    fn sorts_by_every_key() {
+        let documents = vec![
+            doc! { "_id": 1, "age": 30, "name": "b" },
+            doc! { "_id": 2, "age": 20, "name": "c" },
+            doc! { "_id": 3, "age": 30, "name": "a" },
+            doc! { "_id": 4, "name": "d" },
+        ];
+        let sort = doc! { "age": -1, "name": 1 };
+        assert_eq!(sorted_ids(documents.clone(), sort), vec![3, 1, 2, 4]);
+        assert_eq!(sorted_ids(documents, doc! { "age": 1 }), vec![4, 2, 1, 3]);
+    }
+
+    #[test]
+    fn sorts_arrays_by_their_bounds() {
+        let documents = vec![
+            doc! { "_id": 1, "scores": [5, 1] },
+            doc! { "_id": 2, "scores": [3, 4] },
+            doc! { "_id": 3, "scores": 2 },
+        ];
+        assert_eq!(
+            sorted_ids(documents.clone(), doc! { "scores": 1 }),
+            vec![1, 3, 2]
+        );
+        assert_eq!(sorted_ids(documents, doc! { "scores": -1 }), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_invalid_sorts() {
+        assert!(validate_sort(&doc! {}).is_err());
+        assert!(validate_sort(&doc! { "a": 2 }).is_err());
+        assert!(validate_sort(&doc! { "a": "asc" }).is_err());
+        assert!(validate_sort(&doc! { "a": 1, "b": -1.0 }).is_ok());
+    }
+
+    #[test]
+    fn compares_numbers_by_value() {
+        let equal = |a: Bson, b: Bson| values_equal(&a, &b);
+        assert!(equal(Bson::Int32(1), Bson::Int64(1)));
+        assert!(equal(Bson::Int64(1), Bson::Double(1.0)));
+        assert!(equal(Bson::Double(0.0), Bson::Double(-0.0)));
+        assert!(equal(Bson::Double(f64::NAN), Bson::Double(f64::NAN)));
+        assert!(equal(Bson::from(vec![1, 2]), Bson::from(vec![1.0, 2.0])));
+        assert!(equal(
+            Bson::from(doc! { "a": 1 }),
+            Bson::from(doc! { "a": 1_i64 })
+        ));
+        assert!(!equal(Bson::Int32(1), Bson::Double(1.5)));
+
+        let decimal = Bson::Decimal128("2.5".parse().unwrap());
+        assert_eq!(compare_bson(&decimal, &Bson::Double(2.5)), Ordering::Equal);
+        assert_eq!(compare_bson(&decimal, &Bson::Int32(3)), Ordering::Less);
+    }
+
+    #[test]
+    fn compares_large_integers_exactly() {
+        let big = 9_007_199_254_740_993_i64; // 2^53 + 1, which a double rounds down
+        let rounded = Bson::Double(9_007_199_254_740_992.0);
+        assert_eq!(compare_bson(&Bson::Int64(big), &rounded), Ordering::Greater);
+        assert_eq!(compare_bson(&rounded, &Bson::Int64(big)), Ordering::Less);
+        let above = Bson::Double(1e19);
+        assert_eq!(compare_bson(&Bson::Int64(i64::MAX), &above), Ordering::Less);
+        let below = Bson::Double(-1e19);
+        assert_eq!(
+            compare_bson(&Bson::Int64(i64::MIN), &below),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn sorts_nan_before_other_numbers() {
+        let nan = Bson::Double(f64::NAN);
+        assert_eq!(compare_bson(&nan, &Bson::Int32(i32::MIN)), Ordering::Less);
+        assert_eq!(
+            compare_bson(&nan, &Bson::Double(f64::NEG_INFINITY)),
+            Ordering::Less
+        );
+        assert_eq!(compare_bson(&Bson::Int64(0), &nan), Ordering::Greater);
+        assert_eq!(compare_bson(&nan, &Bson::Null), Ordering::Greater);
+    }
+}
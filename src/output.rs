@@ -0,0 +1,139 @@
+use bson::{Bson, Document};
+use serde_json::Value;
+
+/// How documents and values are printed.
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputMode {
+    /// The Display of bson, such as `{ "_id": ObjectId("...") }`.
+    Shell,
+    /// Relaxed Extended JSON on a single line, where numbers and dates stay readable.
+    Relaxed,
+    /// Canonical Extended JSON on a single line, which keeps every type.
+    Canonical,
+    /// Relaxed Extended JSON indented over several lines.
+    Pretty,
+    /// Relaxed Extended JSON without any whitespace.
+    Compact,
+}
+
+pub const OUTPUT_MODES: [&str; 5] = ["shell", "relaxed", "canonical", "pretty", "compact"];
+
+impl OutputMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "shell" => return Some(OutputMode::Shell),
+            "relaxed" => return Some(OutputMode::Relaxed),
+            "canonical" => return Some(OutputMode::Canonical),
+            "pretty" => return Some(OutputMode::Pretty),
+            "compact" => return Some(OutputMode::Compact),
+            _ => return None,
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        match self {
+            OutputMode::Shell => return "shell",
+            OutputMode::Relaxed => return "relaxed",
+            OutputMode::Canonical => return "canonical",
+            OutputMode::Pretty => return "pretty",
+            OutputMode::Compact => return "compact",
+        }
+    }
+}
+
+pub fn format_document(doc: &Document, mode: OutputMode) -> String {
+    return format_value(&Bson::Document(doc.clone()), mode);
+}
+
+pub fn format_value(value: &Bson, mode: OutputMode) -> String {
+    match mode {
+        OutputMode::Shell => return value.to_string(),
+        OutputMode::Relaxed => return single_line(&value.clone().into_relaxed_extjson()),
+        OutputMode::Canonical => return single_line(&value.clone().into_canonical_extjson()),
+        OutputMode::Pretty => {
+            let json = value.clone().into_relaxed_extjson();
+            return serde_json::to_string_pretty(&json).unwrap_or_else(|_e| json.to_string());
+        }
+        OutputMode::Compact => return value.clone().into_relaxed_extjson().to_string(),
+    }
+}
+
+/// Writes json on one line with a space after every colon and comma.
+fn single_line(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let fields: Vec<String> = map
+                .iter()
+                .map(|(key, value)| {
+                    format!("{}: {}", Value::from(key.as_str()), single_line(value))
+                })
+                .collect();
+            return format!("{{{}}}", fields.join(", "));
+        }
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(single_line).collect();
+            return format!("[{}]", items.join(", "));
+        }
+        _ => return value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, Bson, DateTime};
+
+    use super::{format_document, format_value, OutputMode, OUTPUT_MODES};
+
+    #[test]
+    fn names_every_mode() {
+        for name in OUTPUT_MODES {
+            assert_eq!(OutputMode::from_name(name).unwrap().get_name(), name);
+        }
+        assert!(OutputMode::from_name("table").is_none());
+    }
+
+    #[test]
+    fn formats_each_mode() {
+        let doc = doc! { "n": 1, "big": 2_i64, "x": 1.5, "tags": ["a"] };
+        assert_eq!(
+            format_document(&doc, OutputMode::Relaxed),
+            r#"{"n": 1, "big": 2, "x": 1.5, "tags": ["a"]}"#
+        );
+        assert_eq!(
+            format_document(&doc, OutputMode::Canonical),
+            concat!(
+                r#"{"n": {"$numberInt": "1"}, "big": {"$numberLong": "2"}, "#,
+                r#""x": {"$numberDouble": "1.5"}, "tags": ["a"]}"#
+            )
+        );
+        assert_eq!(
+            format_document(&doc, OutputMode::Compact),
+            r#"{"n":1,"big":2,"x":1.5,"tags":["a"]}"#
+        );
+        assert_eq!(
+            format_document(&doc! { "a": { "b": 1 } }, OutputMode::Pretty),
+            "{\n  \"a\": {\n    \"b\": 1\n  }\n}"
+        );
+        assert_eq!(
+            format_document(&doc! { "a": "b" }, OutputMode::Shell),
+            r#"{ "a": "b" }"#
+        );
+    }
+
+    #[test]
+    fn formats_dates_and_plain_values() {
+        let date = Bson::DateTime(DateTime::from_millis(0));
+        assert_eq!(
+            format_value(&date, OutputMode::Relaxed),
+            r#"{"$date": "1970-01-01T00:00:00Z"}"#
+        );
+        assert_eq!(
+            format_value(&date, OutputMode::Canonical),
+            r#"{"$date": {"$numberLong": "0"}}"#
+        );
+        assert_eq!(
+            format_value(&Bson::String("a".to_string()), OutputMode::Relaxed),
+            "\"a\""
+        );
+    }
+}
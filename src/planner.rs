@@ -0,0 +1,227 @@
+use std::ops::Bound;
+
+use bson::{doc, Bson, Document};
+
+use crate::{
+    index::{Index, IndexBounds},
+    text::{TextIndex, TextSearch},
+};
+
+/// How a query reads the documents of a collection.
+#[allow(clippy::large_enum_variant)]
+enum ScanStage {
+    CollectionScan,
+    IndexScan {
+        index: String,
+        bounds: IndexBounds,
+        positions: Vec<usize>,
+    },
+    TextScan {
+        index: String,
+        terms: Vec<String>,
+        positions: Vec<usize>,
+    },
+}
+
+/// The stage chosen for a query and the names of the indexes that could
+/// have been used instead.
+pub struct QueryPlan {
+    stage: ScanStage,
+    rejected: Vec<String>,
+}
+
+/// A `$text` query reads the text index, which ranks the documents by score.
+/// Otherwise the bounds of every index that covers a field of the query are
+/// read and the one with the fewest candidate documents is kept. Without any,
+/// all the documents of the collection are scanned.
+pub fn plan_query(
+    indexes: &[Index],
+    text_index: Option<&TextIndex>,
+    documents: &[Document],
+    query: &Document,
+) -> QueryPlan {
+    let mut stage = ScanStage::CollectionScan;
+    let mut rejected: Vec<String> = Vec::new();
+
+    if let (Some(text_index), Some(search)) = (text_index, query.get("$text")) {
+        let search = TextSearch::parse(search);
+        stage = ScanStage::TextScan {
+            index: text_index.get_name(),
+            positions: text_index.search(documents, &search),
+            terms: search.get_terms().to_owned(),
+        };
+        return QueryPlan { stage, rejected };
+    }
+
+    for index in indexes.iter() {
+        let bounds = match index.bounds_for(query) {
+            Some(bounds) => bounds,
+            None => continue,
+        };
+        let positions = index.positions_in(&bounds);
+        let better = match &stage {
+            ScanStage::IndexScan {
+                positions: best, ..
+            } => positions.len() < best.len(),
+            _ => true,
+        };
+        if !better {
+            rejected.push(index.get_name());
+            continue;
+        }
+
+        let chosen = ScanStage::IndexScan {
+            index: index.get_name(),
+            bounds,
+            positions,
+        };
+        if let ScanStage::IndexScan { index, .. } = std::mem::replace(&mut stage, chosen) {
+            rejected.push(index);
+        }
+    }
+
+    return QueryPlan { stage, rejected };
+}
+
+impl QueryPlan {
+    /// Returns the positions of the documents the plan reads, which still
+    /// have to be checked against the query. A text scan gives them by score.
+    pub fn candidates(&self, num_documents: usize) -> Vec<usize> {
+        match &self.stage {
+            ScanStage::CollectionScan => return (0..num_documents).collect(),
+            ScanStage::IndexScan { positions, .. } | ScanStage::TextScan { positions, .. } => {
+                return positions.clone()
+            }
+        }
+    }
+
+    /// Describes the plan like the `winningPlan` and `rejectedPlans` of MongoDB.
+    pub fn describe(&self) -> Document {
+        let winning = match &self.stage {
+            ScanStage::CollectionScan => doc! { "stage": "COLLSCAN" },
+            ScanStage::IndexScan { index, bounds, .. } => doc! {
+                "stage": "IXSCAN",
+                "indexName": index,
+                "indexBounds": describe_bounds(bounds),
+            },
+            ScanStage::TextScan { index, terms, .. } => doc! {
+                "stage": "TEXT",
+                "indexName": index,
+                "terms": terms,
+            },
+        };
+        let rejected: Vec<Bson> = self
+            .rejected
+            .iter()
+            .map(|index| Bson::Document(doc! { "stage": "IXSCAN", "indexName": index }))
+            .collect();
+
+        return doc! { "winningPlan": winning, "rejectedPlans": rejected };
+    }
+}
+
+/// Writes the bounds as intervals, such as `[5, 5]` or `(18, MaxKey]`.
+fn describe_bounds(bounds: &IndexBounds) -> Vec<String> {
+    match bounds {
+        IndexBounds::Points(values) => {
+            return values
+                .iter()
+                .map(|value| format!("[{}, {}]", value, value))
+                .collect()
+        }
+        IndexBounds::Range(lower, upper) => {
+            let lower = match lower {
+                Bound::Included(value) => format!("[{}", value),
+                Bound::Excluded(value) => format!("({}", value),
+                Bound::Unbounded => "[MinKey".to_string(),
+            };
+            let upper = match upper {
+                Bound::Included(value) => format!("{}]", value),
+                Bound::Excluded(value) => format!("{})", value),
+                Bound::Unbounded => "MaxKey]".to_string(),
+            };
+            return vec![format!("{}, {}", lower, upper)];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, Bson, Document};
+
+    use super::plan_query;
+    use crate::{index::Index, text::TextIndex};
+
+    fn documents() -> Vec<Document> {
+        return vec![
+            doc! { "city": "Oslo", "age": 20, "bio": "likes running" },
+            doc! { "city": "Oslo", "age": 30, "bio": "likes chess" },
+            doc! { "city": "Rome", "age": 30, "bio": "runs every day" },
+        ];
+    }
+
+    fn index_on(field: &str, documents: &[Document]) -> Index {
+        let mut index = Index::new(format!("{}_1", field), doc! { field: 1 }, &Document::new());
+        index.rebuild(documents);
+        return index;
+    }
+
+    fn winning_stage(plan: &Document) -> (String, Option<String>) {
+        let winning = plan.get_document("winningPlan").unwrap();
+        let stage = winning.get_str("stage").unwrap().to_string();
+        let index = winning
+            .get_str("indexName")
+            .ok()
+            .map(|name| name.to_string());
+        return (stage, index);
+    }
+
+    #[test]
+    fn scans_the_collection_without_a_usable_index() {
+        let documents = documents();
+        let indexes = vec![index_on("city", &documents)];
+        let plan = plan_query(&indexes, None, &documents, &doc! { "age": 30 });
+
+        assert_eq!(plan.candidates(documents.len()), vec![0, 1, 2]);
+        let description = plan.describe();
+        assert_eq!(winning_stage(&description), ("COLLSCAN".to_string(), None));
+        assert!(description.get_array("rejectedPlans").unwrap().is_empty());
+    }
+
+    #[test]
+    fn keeps_the_index_with_fewest_candidates() {
+        let documents = documents();
+        let indexes = vec![index_on("city", &documents), index_on("age", &documents)];
+        let query = doc! { "city": "Oslo", "age": { "$lt": 25 } };
+        let plan = plan_query(&indexes, None, &documents, &query);
+
+        assert_eq!(plan.candidates(documents.len()), vec![0]);
+        let description = plan.describe();
+        let expected = ("IXSCAN".to_string(), Some("age_1".to_string()));
+        assert_eq!(winning_stage(&description), expected);
+        let winning = description.get_document("winningPlan").unwrap();
+        let bounds = vec![Bson::String("[MinKey, 25)".to_string())];
+        assert_eq!(winning.get_array("indexBounds").unwrap(), &bounds);
+        let rejected = vec![Bson::Document(
+            doc! { "stage": "IXSCAN", "indexName": "city_1" },
+        )];
+        assert_eq!(description.get_array("rejectedPlans").unwrap(), &rejected);
+    }
+
+    #[test]
+    fn reads_the_text_index_for_text_queries() {
+        let documents = documents();
+        let indexes = vec![index_on("city", &documents)];
+        let mut text_index = TextIndex::new("bio_text".to_string(), doc! { "bio": "text" });
+        text_index.rebuild(&documents);
+        let query = doc! { "city": "Oslo", "$text": { "$search": "running" } };
+        let plan = plan_query(&indexes, Some(&text_index), &documents, &query);
+
+        let mut candidates = plan.candidates(documents.len());
+        candidates.sort_unstable();
+        assert_eq!(candidates, vec![0, 2]);
+        let description = plan.describe();
+        let expected = ("TEXT".to_string(), Some("bio_text".to_string()));
+        assert_eq!(winning_stage(&description), expected);
+    }
+}
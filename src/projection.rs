@@ -0,0 +1,371 @@
+use bson::{Bson, Document};
+
+use crate::filter::as_integer;
+
+/// Checks that the projection only includes or only excludes fields (besides
+/// `_id`) and that every `$slice` has a valid operand.
+pub fn validate_projection(projection: &Document) -> Result<(), String> {
+    let spec = build_spec(projection);
+    let mut including = false;
+    let mut excluding = false;
+    check_modes(&spec, true, &mut including, &mut excluding)?;
+
+    if including && excluding {
+        return Err("A projection cant mix included and excluded fields".to_string());
+    }
+
+    return Ok(());
+}
+
+fn check_modes(
+    spec: &Document,
+    top_level: bool,
+    including: &mut bool,
+    excluding: &mut bool,
+) -> Result<(), String> {
+    for (key, value) in spec.iter() {
+        match value {
+            Bson::Document(inner) if is_slice(inner) => {
+                if parse_slice(inner).is_none() {
+                    return Err(format!("Invalid '$slice' for '{}'", key));
+                }
+            }
+            Bson::Document(inner) if is_meta(inner) => {
+                if !top_level {
+                    return Err(format!("'$meta' cant be used inside '{}'", key));
+                }
+                if inner.get_str("$meta") != Ok("textScore") {
+                    return Err(format!("Unknown '$meta' for '{}'", key));
+                }
+            }
+            Bson::Document(inner) => {
+                if inner.keys().any(|key| key.starts_with('$')) {
+                    return Err(format!("Unknown projection operator for '{}'", key));
+                }
+                check_modes(inner, false, including, excluding)?;
+            }
+            _ if top_level && key == "_id" => {}
+            _ => {
+                if is_truthy(value) {
+                    *including = true;
+                } else {
+                    *excluding = true;
+                }
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+/// Returns the projected copy of the document. The projection has to be valid.
+pub fn apply_projection(doc: &Document, projection: &Document) -> Document {
+    let spec = build_spec(projection);
+
+    let only_id = spec.len() == 1 && matches!(spec.get("_id"), Some(value) if is_truthy(value));
+    if only_id || is_inclusion(&spec) {
+        let mut projected = include_fields(doc, &spec);
+        if !spec.contains_key("_id") {
+            if let Some(id) = doc.get("_id") {
+                let mut with_id = Document::new();
+                with_id.insert("_id", id.clone());
+                with_id.extend(projected);
+                projected = with_id;
+            }
+        }
+        return projected;
+    }
+
+    return exclude_fields(doc, &spec);
+}
+
+/// Turns dotted keys such as "address.city" into nested projection documents.
+fn build_spec(projection: &Document) -> Document {
+    let mut spec = Document::new();
+    for (key, value) in projection.iter() {
+        insert_spec(&mut spec, key, value.clone());
+    }
+
+    return spec;
+}
+
+fn insert_spec(spec: &mut Document, path: &str, value: Bson) {
+    match path.split_once('.') {
+        None => match (spec.get_mut(path), value) {
+            (Some(Bson::Document(existing)), Bson::Document(inner)) if !is_slice(&inner) => {
+                for (key, value) in inner {
+                    insert_spec(existing, &key, value);
+                }
+            }
+            (_, Bson::Document(inner)) if !is_slice(&inner) => {
+                spec.insert(path, build_spec(&inner));
+            }
+            (_, value) => {
+                spec.insert(path, value);
+            }
+        },
+        Some((head, rest)) => {
+            if !matches!(spec.get(head), Some(Bson::Document(inner)) if !is_slice(inner)) {
+                spec.insert(head, Document::new());
+            }
+            if let Some(Bson::Document(inner)) = spec.get_mut(head) {
+                insert_spec(inner, rest, value);
+            }
+        }
+    }
+}
+
+fn is_inclusion(spec: &Document) -> bool {
+    for (key, value) in spec.iter() {
+        match value {
+            Bson::Document(inner) if is_slice(inner) || is_meta(inner) => {}
+            Bson::Document(inner) => {
+                if is_inclusion(inner) {
+                    return true;
+                }
+            }
+            _ if key == "_id" => {}
+            _ => {
+                if is_truthy(value) {
+                    return true;
+                }
+            }
+        }
+    }
+
+    return false;
+}
+
+fn include_fields(doc: &Document, spec: &Document) -> Document {
+    let mut projected = Document::new();
+    for (key, value) in doc.iter() {
+        let rule = match spec.get(key) {
+            Some(rule) => rule,
+            None => continue,
+        };
+        match rule {
+            Bson::Document(inner) if is_slice(inner) => {
+                projected.insert(key, slice_value(value, inner));
+            }
+            Bson::Document(inner) if is_meta(inner) => {}
+            Bson::Document(inner) => match value {
+                Bson::Document(child) => {
+                    projected.insert(key, include_fields(child, inner));
+                }
+                Bson::Array(items) => {
+                    let items: Vec<Bson> = items
+                        .iter()
+                        .filter_map(|item| item.as_document())
+                        .map(|item| Bson::Document(include_fields(item, inner)))
+                        .collect();
+                    projected.insert(key, items);
+                }
+                _ => {}
+            },
+            _ => {
+                if is_truthy(rule) {
+                    projected.insert(key, value.clone());
+                }
+            }
+        }
+    }
+
+    return projected;
+}
+
+fn exclude_fields(doc: &Document, spec: &Document) -> Document {
+    let mut projected = Document::new();
+    for (key, value) in doc.iter() {
+        let rule = match spec.get(key) {
+            Some(rule) => rule,
+            None => {
+                projected.insert(key, value.clone());
+                continue;
+            }
+        };
+        match rule {
+            Bson::Document(inner) if is_slice(inner) => {
+                projected.insert(key, slice_value(value, inner));
+            }
+            Bson::Document(inner) if is_meta(inner) => {}
+            Bson::Document(inner) => match value {
+                Bson::Document(child) => {
+                    projected.insert(key, exclude_fields(child, inner));
+                }
+                Bson::Array(items) => {
+                    let items: Vec<Bson> = items
+                        .iter()
+                        .map(|item| match item {
+                            Bson::Document(item) => Bson::Document(exclude_fields(item, inner)),
+                            _ => item.clone(),
+                        })
+                        .collect();
+                    projected.insert(key, items);
+                }
+                _ => {
+                    projected.insert(key, value.clone());
+                }
+            },
+            _ => {
+                if is_truthy(rule) {
+                    projected.insert(key, value.clone());
+                }
+            }
+        }
+    }
+
+    return projected;
+}
+
+fn is_slice(rule: &Document) -> bool {
+    return rule.len() == 1 && rule.contains_key("$slice");
+}
+
+fn is_meta(rule: &Document) -> bool {
+    return rule.len() == 1 && rule.contains_key("$meta");
+}
+
+/// Returns the fields set to `{"$meta": "textScore"}`, which are filled with the
+/// score of the `$text` search instead of a value of the document.
+pub fn meta_fields(projection: &Document) -> Vec<String> {
+    let mut fields: Vec<String> = Vec::new();
+    for (key, value) in projection.iter() {
+        if matches!(value, Bson::Document(inner) if is_meta(inner)) {
+            fields.push(key.to_owned());
+        }
+    }
+
+    return fields;
+}
+
+/// Reads a `$slice` operand, either a count (negative counts from the end) or
+/// a `[skip, limit]` pair, as a skip (maybe negative) and an optional limit.
+fn parse_slice(rule: &Document) -> Option<(i64, Option<i64>)> {
+    match rule.get("$slice")? {
+        Bson::Int32(count) => return Some(slice_from_count(*count as i64)),
+        Bson::Int64(count) => return Some(slice_from_count(*count)),
+        Bson::Array(pair) if pair.len() == 2 => {
+            let skip = as_integer(&pair[0])?;
+            let limit = as_integer(&pair[1])?;
+            if limit <= 0 {
+                return None;
+            }
+            return Some((skip, Some(limit)));
+        }
+        _ => return None,
+    }
+}
+
+fn slice_from_count(count: i64) -> (i64, Option<i64>) {
+    if count < 0 {
+        return (count, None);
+    }
+
+    return (0, Some(count));
+}
+
+fn slice_value(value: &Bson, rule: &Document) -> Bson {
+    let items = match value {
+        Bson::Array(items) => items,
+        _ => return value.clone(),
+    };
+    let (skip, limit) = match parse_slice(rule) {
+        Some(slice) => slice,
+        None => return value.clone(),
+    };
+
+    let len = items.len() as i64;
+    let start = if skip < 0 {
+        (len + skip).max(0)
+    } else {
+        skip.min(len)
+    };
+    let end = match limit {
+        Some(limit) => start.saturating_add(limit).min(len),
+        None => len,
+    };
+
+    return Bson::Array(items[start as usize..end as usize].to_vec());
+}
+
+pub fn is_truthy(value: &Bson) -> bool {
+    match value {
+        Bson::Boolean(value) => return *value,
+        Bson::Int32(number) => return *number != 0,
+        Bson::Int64(number) => return *number != 0,
+        Bson::Double(number) => return *number != 0.0,
+        Bson::Null => return false,
+        _ => return true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{bson, doc, Document};
+
+    use super::{apply_projection, validate_projection};
+
+    fn projected(doc: &Document, projection: Document) -> Document {
+        assert!(validate_projection(&projection).is_ok());
+        return apply_projection(doc, &projection);
+    }
+
+    fn sample() -> Document {
+        return doc! {
+            "_id": 1,
+            "name": "Ada",
+            "address": { "city": "London", "zip": "N1" },
+            "comments": [1, 2, 3, 4, 5],
+        };
+    }
+
+    #[test]
+    fn includes_fields() {
+        let doc = sample();
+        let expected = doc! { "_id": 1, "name": "Ada", "address": { "city": "London" } };
+        assert_eq!(
+            projected(&doc, doc! { "name": 1, "address.city": 1 }),
+            expected
+        );
+        assert_eq!(
+            projected(&doc, doc! { "name": true, "_id": 0 }),
+            doc! { "name": "Ada" }
+        );
+        assert_eq!(projected(&doc, doc! { "_id": 1 }), doc! { "_id": 1 });
+    }
+
+    #[test]
+    fn excludes_fields() {
+        let doc = sample();
+        let expected = doc! { "_id": 1, "address": { "zip": "N1" }, "comments": [1, 2, 3, 4, 5] };
+        assert_eq!(
+            projected(&doc, doc! { "name": 0, "address.city": 0 }),
+            expected
+        );
+    }
+
+    #[test]
+    fn slices_arrays() {
+        let doc = sample();
+        let slice = |operand| {
+            let projection = doc! { "comments": { "$slice": operand }, "name": 0 };
+            return projected(&doc, projection).get("comments").cloned();
+        };
+        assert_eq!(slice(bson!(2)), Some(bson!([1, 2])));
+        assert_eq!(slice(bson!(-2)), Some(bson!([4, 5])));
+        assert_eq!(slice(bson!([1, 2])), Some(bson!([2, 3])));
+        assert_eq!(slice(bson!([-2, 5])), Some(bson!([4, 5])));
+        assert_eq!(slice(bson!([10, 1])), Some(bson!([])));
+        assert_eq!(slice(bson!([2, i64::MAX])), Some(bson!([3, 4, 5])));
+        assert_eq!(slice(bson!(i64::MIN)), Some(bson!([1, 2, 3, 4, 5])));
+    }
+
+    #[test]
+    fn rejects_invalid_projections() {
+        assert!(validate_projection(&doc! { "a": 1, "b": 0 }).is_err());
+        assert!(validate_projection(&doc! { "a": 1, "_id": 0 }).is_ok());
+        assert!(validate_projection(&doc! { "a": { "$slice": "2" } }).is_err());
+        assert!(validate_projection(&doc! { "a": { "$slice": [1, 0] } }).is_err());
+        assert!(validate_projection(&doc! { "a": { "$elemMatch": {} } }).is_err());
+    }
+}
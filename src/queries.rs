@@ -1,13 +1,26 @@
-use bson::Document;
+use std::time::Instant;
+
+use bson::{Bson, Document};
 
 use crate::{
-    bson_functions::string_to_document, collection::Collection, get_collection,
-    statement::StatementType, CollectionResult, Database, ExecuteResult, PrepareResult, Statement,
-    TABLE_MAX_DOCUMENTS,
+    aggregation::{run_pipeline, validate_pipeline},
+    bson_functions::{string_to_bson, string_to_document},
+    collection::{Collection, WriteError},
+    filter::Filter,
+    get_collection,
+    index::{index_name, validate_index_keys, validate_index_options},
+    lexer::{LexError, Token},
+    ordering::validate_sort,
+    output::{format_document, format_value},
+    projection::{apply_projection, meta_fields, validate_projection},
+    settings::Settings,
+    statement::StatementType,
+    update::{apply_update, document_from_filter, validate_replacement, validate_update},
+    CollectionResult, Database, ExecuteResult, PrepareResult, Statement, TABLE_MAX_DOCUMENTS,
 };
 
 pub fn prepare_insert(
-    input_parsed: Vec<&str>,
+    input_parsed: &[Token],
     statement: &mut Statement,
     database: &mut Database,
 ) -> PrepareResult {
@@ -15,7 +28,7 @@ pub fn prepare_insert(
         return PrepareResult::PrepareMissingCollection;
     }
 
-    let collection_name = input_parsed[1];
+    let collection_name = input_parsed[1].get_text();
     match get_collection(statement, database, collection_name) {
         CollectionResult::CollectionDoesntExist => {
             return PrepareResult::PrepareCollectionDoesntExist
@@ -23,21 +36,19 @@ pub fn prepare_insert(
         CollectionResult::CollectionSuccess => {}
     }
 
-    if input_parsed.len() < 3 {
+    if input_parsed.len() != 3 {
         return PrepareResult::PrepareSyntaxError;
     }
 
     statement.set_type(StatementType::StatementInsert);
 
-    let json_input = input_parsed[2..].join("");
-
-    match string_to_document(&json_input) {
+    match token_to_document(&input_parsed[2]) {
         Ok(document) => {
-            statement.set_row_to_insert(Document::from(document));
+            statement.set_row_to_insert(document);
             return PrepareResult::PrepareSuccess;
         }
-        Err(_err) => {
-            return PrepareResult::PrepareCantParseJson;
+        Err(err) => {
+            return PrepareResult::PrepareCantParseJson(err);
         }
     }
 }
@@ -55,12 +66,12 @@ pub fn execute_peek(database: &mut Database) -> ExecuteResult {
     return ExecuteResult::ExecuteSuccess;
 }
 
-pub fn prepare_create(input_parsed: Vec<&str>, statement: &mut Statement) -> PrepareResult {
+pub fn prepare_create(input_parsed: &[Token], statement: &mut Statement) -> PrepareResult {
     if input_parsed.len() < 2 {
         return PrepareResult::PrepareMissingCollection;
     }
 
-    let collection_name = input_parsed[1];
+    let collection_name = input_parsed[1].get_text();
     statement.set_type(StatementType::StatementCreate);
     statement.set_collection_name(collection_name.to_owned());
     return PrepareResult::PrepareSuccess;
@@ -85,10 +96,9 @@ pub fn execute_insert(statement: Statement, database: &mut Database) -> ExecuteR
 
     let collections: &mut Vec<Collection> = database.get_collections();
 
-    for i in 0..collections.len() {
-        let item = &collections[i];
+    for item in collections.iter_mut() {
         if item.get_name().eq(&statement.get_collection()) {
-            table = Some(&mut collections[i]);
+            table = Some(item);
             break; // Exit loop once the desired item is found
         }
     }
@@ -101,7 +111,10 @@ pub fn execute_insert(statement: Statement, database: &mut Database) -> ExecuteR
 
             let row_to_insert: Document = statement.get_row_to_insert();
 
-            collection.add_to_collection(row_to_insert);
+            match collection.add_to_collection(row_to_insert) {
+                Ok(id) => println!("Inserted {}", id),
+                Err(err) => return write_error_result(err),
+            }
             return ExecuteResult::ExecuteSuccess;
         }
         None => return ExecuteResult::ExecuteTableUndefined,
@@ -109,7 +122,7 @@ pub fn execute_insert(statement: Statement, database: &mut Database) -> ExecuteR
 }
 
 pub fn prepare_find(
-    input_parsed: Vec<&str>,
+    input_parsed: &[Token],
     statement: &mut Statement,
     database: &mut Database,
 ) -> PrepareResult {
@@ -117,44 +130,152 @@ pub fn prepare_find(
         return PrepareResult::PrepareMissingCollection;
     }
 
-    let collection_name = input_parsed[1];
+    let collection_name = input_parsed[1].get_text();
     match get_collection(statement, database, collection_name) {
         CollectionResult::CollectionDoesntExist => {
             return PrepareResult::PrepareCollectionDoesntExist
         }
         CollectionResult::CollectionSuccess => {}
     }
-    let json_input = input_parsed[2..].join("");
+    let arguments = &input_parsed[2..];
+    let documents = arguments
+        .iter()
+        .take_while(|argument| argument.is_json())
+        .count();
+    if documents > 2 {
+        return PrepareResult::PrepareSyntaxError;
+    }
+
     statement.set_type(StatementType::StatementFind);
-    match string_to_document(&json_input) {
+    // Without a filter every document matches
+    let filter = match documents {
+        0 => Ok(Document::new()),
+        _ => token_to_document(&arguments[0]),
+    };
+    match filter {
         Ok(document) => {
-            statement.set_row_to_insert(Document::from(document));
-            return PrepareResult::PrepareSuccess;
+            if let Err(err) = prepare_statement_filter(document, statement, database) {
+                return PrepareResult::PrepareInvalidFilter(err);
+            }
         }
-        Err(_err) => {
-            return PrepareResult::PrepareCantParseJson;
+        Err(err) => {
+            return PrepareResult::PrepareCantParseJson(err);
         }
     }
+
+    if documents == 2 {
+        match token_to_document(&arguments[1]) {
+            Ok(document) => {
+                if let Err(err) = validate_projection(&document) {
+                    return PrepareResult::PrepareInvalidProjection(err);
+                }
+                let has_text = statement.get_filter().get_document().contains_key("$text");
+                if !has_text && !meta_fields(&document).is_empty() {
+                    let err = "'$meta' needs a '$text' query".to_string();
+                    return PrepareResult::PrepareInvalidProjection(err);
+                }
+                statement.set_projection(document);
+            }
+            Err(err) => {
+                return PrepareResult::PrepareCantParseJson(err);
+            }
+        }
+    }
+
+    return prepare_find_modifiers(&arguments[documents..], statement);
 }
 
-pub fn execute_find(statement: Statement, database: &mut Database) -> ExecuteResult {
+/// Checks the filter of a statement, where `$text` needs a text index on the
+/// collection, and keeps it in the statement with its regular expressions compiled.
+fn prepare_statement_filter(
+    filter: Document,
+    statement: &mut Statement,
+    database: &Database,
+) -> Result<(), String> {
+    let filter = Filter::new(filter)?;
+    if filter.get_document().contains_key("$text") {
+        match database.find_collection(&statement.get_collection()) {
+            Some(collection) if collection.has_text_index() => {}
+            _ => return Err("A '$text' query needs a text index".to_string()),
+        }
+    }
+
+    statement.set_filter(filter);
+    return Ok(());
+}
+
+/// Reads the `sort {..}`, `skip n` and `limit n` modifiers that can follow the
+/// filter and projection of a find.
+fn prepare_find_modifiers(modifiers: &[Token], statement: &mut Statement) -> PrepareResult {
+    for pair in modifiers.chunks(2) {
+        if pair.len() != 2 {
+            return PrepareResult::PrepareSyntaxError;
+        }
+        let value = pair[1].get_text();
+        match pair[0].get_text() {
+            "sort" => match token_to_document(&pair[1]) {
+                Ok(document) => {
+                    if let Err(err) = validate_sort(&document) {
+                        return PrepareResult::PrepareInvalidSort(err);
+                    }
+                    statement.set_sort(document);
+                }
+                Err(err) => return PrepareResult::PrepareCantParseJson(err),
+            },
+            "skip" => match value.parse::<usize>() {
+                Ok(skip) => statement.set_skip(skip),
+                Err(_err) => return PrepareResult::PrepareSyntaxError,
+            },
+            "limit" => match value.parse::<usize>() {
+                Ok(limit) => statement.set_limit(limit),
+                Err(_err) => return PrepareResult::PrepareSyntaxError,
+            },
+            _ => return PrepareResult::PrepareSyntaxError,
+        }
+    }
+
+    return PrepareResult::PrepareSuccess;
+}
+
+pub fn execute_find(
+    statement: Statement,
+    database: &mut Database,
+    settings: &Settings,
+) -> ExecuteResult {
     let mut table: Option<&Collection> = None; //TODO move a collection reference inside statement
 
     let collections: &mut Vec<Collection> = database.get_collections();
 
-    for i in 0..collections.len() {
-        let item = &collections[i];
+    for item in collections.iter_mut() {
         if item.get_name().eq(&statement.get_collection()) {
-            table = Some(&mut collections[i]);
+            table = Some(item);
             break;
         }
     }
 
+    let output_mode = settings.get_output_mode();
     match table {
         Some(collection) => {
-            let documents = collection.simple_search(statement.get_row_to_insert());
-            for i in documents.iter() {
-                println!("{}", i);
+            let documents = match statement.get_sort() {
+                Some(sort) => collection.get_sorted_collection(statement.get_filter(), sort),
+                None => collection.simple_search(statement.get_filter()),
+            };
+            let limit = match statement.get_limit() {
+                0 => documents.len(),
+                limit => limit,
+            };
+            for i in documents.iter().skip(statement.get_skip()).take(limit) {
+                match statement.get_projection() {
+                    Some(projection) => {
+                        let mut projected = apply_projection(i, projection);
+                        for field in meta_fields(projection) {
+                            let filter = statement.get_filter().get_document();
+                            projected.insert(field, collection.text_score(i, filter));
+                        }
+                        println!("{}", format_document(&projected, output_mode))
+                    }
+                    None => println!("{}", format_document(i, output_mode)),
+                }
             }
         }
         None => return ExecuteResult::ExecuteTableUndefined,
@@ -163,8 +284,60 @@ pub fn execute_find(statement: Statement, database: &mut Database) -> ExecuteRes
     return ExecuteResult::ExecuteSuccess;
 }
 
+/// Prepares `explain find <collection> [filter]` like the find it explains.
+pub fn prepare_explain(
+    input_parsed: &[Token],
+    statement: &mut Statement,
+    database: &mut Database,
+) -> PrepareResult {
+    if input_parsed.get(1).map(|token| token.get_text()) != Some("find") {
+        return PrepareResult::PrepareSyntaxError;
+    }
+
+    let result = prepare_find(&input_parsed[1..], statement, database);
+    if let PrepareResult::PrepareSuccess = result {
+        statement.set_type(StatementType::StatementExplain);
+    }
+    return result;
+}
+
+/// Runs the plan of a find without printing its documents, and prints the
+/// chosen plan, how many documents it examined and returned, and the time it took.
+pub fn execute_explain(
+    statement: Statement,
+    database: &mut Database,
+    settings: &Settings,
+) -> ExecuteResult {
+    let collection = match database.find_collection(&statement.get_collection()) {
+        Some(collection) => collection,
+        None => return ExecuteResult::ExecuteTableUndefined,
+    };
+
+    let filter = statement.get_filter();
+    let start = Instant::now();
+    let plan = collection.plan(filter.get_document());
+    let (positions, examined) = collection.execute_plan(&plan, filter);
+    let elapsed = start.elapsed();
+
+    let mut returned = positions.len().saturating_sub(statement.get_skip());
+    if statement.get_limit() > 0 {
+        returned = returned.min(statement.get_limit());
+    }
+
+    let mut explain = Document::new();
+    explain.insert("namespace", statement.get_collection());
+    explain.insert("filter", filter.get_document().clone());
+    explain.extend(plan.describe());
+    explain.insert("docsExamined", examined as i64);
+    explain.insert("nReturned", returned as i64);
+    explain.insert("executionTimeMillis", elapsed.as_secs_f64() * 1000.0);
+    println!("{}", format_document(&explain, settings.get_output_mode()));
+
+    return ExecuteResult::ExecuteSuccess;
+}
+
 pub fn prepare_delete(
-    input_parsed: Vec<&str>,
+    input_parsed: &[Token],
     statement: &mut Statement,
     database: &mut Database,
 ) -> PrepareResult {
@@ -172,22 +345,27 @@ pub fn prepare_delete(
         return PrepareResult::PrepareMissingCollection;
     }
 
-    let collection_name = input_parsed[1];
+    let collection_name = input_parsed[1].get_text();
     match get_collection(statement, database, collection_name) {
         CollectionResult::CollectionDoesntExist => {
             return PrepareResult::PrepareCollectionDoesntExist
         }
         CollectionResult::CollectionSuccess => {}
     }
-    let json_input = input_parsed[2..].join("");
+    if input_parsed.len() != 3 {
+        return PrepareResult::PrepareSyntaxError;
+    }
+
     statement.set_type(StatementType::StatementDelete);
-    match string_to_document(&json_input) {
+    match token_to_document(&input_parsed[2]) {
         Ok(document) => {
-            statement.set_row_to_insert(Document::from(document));
+            if let Err(err) = prepare_statement_filter(document, statement, database) {
+                return PrepareResult::PrepareInvalidFilter(err);
+            }
             return PrepareResult::PrepareSuccess;
         }
-        Err(_err) => {
-            return PrepareResult::PrepareCantParseJson;
+        Err(err) => {
+            return PrepareResult::PrepareCantParseJson(err);
         }
     }
 }
@@ -197,20 +375,471 @@ pub fn execute_delete(statement: Statement, database: &mut Database) -> ExecuteR
 
     let collections: &mut Vec<Collection> = database.get_collections();
 
-    for i in 0..collections.len() {
-        let item = &collections[i];
+    for item in collections.iter_mut() {
         if item.get_name().eq(&statement.get_collection()) {
-            table = Some(&mut collections[i]);
+            table = Some(item);
             break;
         }
     }
 
     match table {
         Some(collection) => {
-            collection.simple_delete(statement.get_row_to_insert());
-            println!("Deleted.");
+            let count = collection.simple_delete(statement.get_filter());
+            println!("Deleted {} documents.", count);
         }
         None => return ExecuteResult::ExecuteTableUndefined,
     }
     return ExecuteResult::ExecuteSuccess;
 }
+
+pub fn prepare_update(
+    input_parsed: &[Token],
+    statement: &mut Statement,
+    database: &mut Database,
+    statement_type: StatementType,
+    multi: bool,
+) -> PrepareResult {
+    if input_parsed.len() < 2 {
+        return PrepareResult::PrepareMissingCollection;
+    }
+
+    let collection_name = input_parsed[1].get_text();
+    match get_collection(statement, database, collection_name) {
+        CollectionResult::CollectionDoesntExist => {
+            return PrepareResult::PrepareCollectionDoesntExist
+        }
+        CollectionResult::CollectionSuccess => {}
+    }
+
+    let arguments = &input_parsed[2..];
+    match arguments.len() {
+        2 => statement.set_upsert(false),
+        3 if arguments[2].get_text() == "upsert" => statement.set_upsert(true),
+        _ => return PrepareResult::PrepareSyntaxError,
+    }
+
+    statement.set_type(statement_type);
+    statement.set_multi(multi);
+
+    match token_to_document(&arguments[0]) {
+        Ok(document) => {
+            if let Err(err) = prepare_statement_filter(document, statement, database) {
+                return PrepareResult::PrepareInvalidFilter(err);
+            }
+        }
+        Err(err) => {
+            return PrepareResult::PrepareCantParseJson(err);
+        }
+    }
+
+    match token_to_document(&arguments[1]) {
+        Ok(document) => {
+            let validation = match statement_type {
+                StatementType::StatementReplace => validate_replacement(&document),
+                _ => validate_update(&document),
+            };
+            if let Err(err) = validation {
+                return PrepareResult::PrepareInvalidUpdate(err);
+            }
+            statement.set_update_document(document);
+            return PrepareResult::PrepareSuccess;
+        }
+        Err(err) => {
+            return PrepareResult::PrepareCantParseJson(err);
+        }
+    }
+}
+
+pub fn execute_update(statement: Statement, database: &mut Database) -> ExecuteResult {
+    let mut table: Option<&mut Collection> = None;
+
+    let collections: &mut Vec<Collection> = database.get_collections();
+
+    for item in collections.iter_mut() {
+        if item.get_name().eq(&statement.get_collection()) {
+            table = Some(item);
+            break;
+        }
+    }
+
+    match table {
+        Some(collection) => {
+            let result = match statement.get_type() {
+                StatementType::StatementReplace => collection
+                    .replace_document(statement.get_filter(), statement.get_update_document()),
+                _ => collection.update_documents(
+                    statement.get_filter(),
+                    statement.get_update_document(),
+                    statement.get_multi(),
+                ),
+            };
+            match result {
+                Ok(0) if statement.get_upsert() => return execute_upsert(statement, collection),
+                Ok(count) => println!("Updated {} documents.", count),
+                Err(err) => return write_error_result(err),
+            }
+        }
+        None => return ExecuteResult::ExecuteTableUndefined,
+    }
+    return ExecuteResult::ExecuteSuccess;
+}
+
+fn execute_upsert(statement: Statement, collection: &mut Collection) -> ExecuteResult {
+    if collection.get_num_docuents() >= TABLE_MAX_DOCUMENTS {
+        return ExecuteResult::ExecuteTableFull;
+    }
+
+    let mut document = document_from_filter(statement.get_filter().get_document());
+    match statement.get_type() {
+        StatementType::StatementReplace => {
+            for (key, value) in statement.get_update_document() {
+                document.insert(key, value);
+            }
+        }
+        _ => {
+            if let Err(err) = apply_update(&mut document, &statement.get_update_document()) {
+                return ExecuteResult::ExecuteUpdateFailed(err);
+            }
+        }
+    }
+
+    match collection.add_to_collection(document) {
+        Ok(id) => println!("Upserted {}", id),
+        Err(err) => return write_error_result(err),
+    }
+    return ExecuteResult::ExecuteSuccess;
+}
+
+fn write_error_result(err: WriteError) -> ExecuteResult {
+    match err {
+        WriteError::DuplicateId(id) => return ExecuteResult::ExecuteDuplicateId(id.to_string()),
+        WriteError::DuplicateKey(key) => return ExecuteResult::ExecuteDuplicateKey(key),
+        WriteError::Failed(err) => return ExecuteResult::ExecuteUpdateFailed(err),
+    }
+}
+
+pub fn prepare_count(
+    input_parsed: &[Token],
+    statement: &mut Statement,
+    database: &mut Database,
+) -> PrepareResult {
+    if input_parsed.len() < 2 {
+        return PrepareResult::PrepareMissingCollection;
+    }
+
+    let collection_name = input_parsed[1].get_text();
+    match get_collection(statement, database, collection_name) {
+        CollectionResult::CollectionDoesntExist => {
+            return PrepareResult::PrepareCollectionDoesntExist
+        }
+        CollectionResult::CollectionSuccess => {}
+    }
+
+    statement.set_type(StatementType::StatementCount);
+    match input_parsed.len() {
+        2 => return PrepareResult::PrepareSuccess,
+        3 => {}
+        _ => return PrepareResult::PrepareSyntaxError,
+    }
+
+    match token_to_document(&input_parsed[2]) {
+        Ok(document) => {
+            if let Err(err) = prepare_statement_filter(document, statement, database) {
+                return PrepareResult::PrepareInvalidFilter(err);
+            }
+            return PrepareResult::PrepareSuccess;
+        }
+        Err(err) => {
+            return PrepareResult::PrepareCantParseJson(err);
+        }
+    }
+}
+
+pub fn execute_count(statement: Statement, database: &mut Database) -> ExecuteResult {
+    for item in database.get_collections().iter() {
+        if item.get_name().eq(&statement.get_collection()) {
+            println!("{}", item.count_documents(statement.get_filter()));
+            return ExecuteResult::ExecuteSuccess;
+        }
+    }
+
+    return ExecuteResult::ExecuteTableUndefined;
+}
+
+pub fn prepare_distinct(
+    input_parsed: &[Token],
+    statement: &mut Statement,
+    database: &mut Database,
+) -> PrepareResult {
+    if input_parsed.len() < 2 {
+        return PrepareResult::PrepareMissingCollection;
+    }
+
+    let collection_name = input_parsed[1].get_text();
+    match get_collection(statement, database, collection_name) {
+        CollectionResult::CollectionDoesntExist => {
+            return PrepareResult::PrepareCollectionDoesntExist
+        }
+        CollectionResult::CollectionSuccess => {}
+    }
+
+    if input_parsed.len() < 3 || input_parsed[2].is_json() || input_parsed[2].get_text().is_empty()
+    {
+        return PrepareResult::PrepareSyntaxError;
+    }
+
+    statement.set_type(StatementType::StatementDistinct);
+    statement.set_field(input_parsed[2].get_text().to_owned());
+    match input_parsed.len() {
+        3 => return PrepareResult::PrepareSuccess,
+        4 => {}
+        _ => return PrepareResult::PrepareSyntaxError,
+    }
+
+    match token_to_document(&input_parsed[3]) {
+        Ok(document) => {
+            if let Err(err) = prepare_statement_filter(document, statement, database) {
+                return PrepareResult::PrepareInvalidFilter(err);
+            }
+            return PrepareResult::PrepareSuccess;
+        }
+        Err(err) => {
+            return PrepareResult::PrepareCantParseJson(err);
+        }
+    }
+}
+
+pub fn execute_distinct(
+    statement: Statement,
+    database: &mut Database,
+    settings: &Settings,
+) -> ExecuteResult {
+    for item in database.get_collections().iter() {
+        if item.get_name().eq(&statement.get_collection()) {
+            let values = item.distinct_values(&statement.get_field(), statement.get_filter());
+            println!(
+                "{}",
+                format_value(&Bson::Array(values), settings.get_output_mode())
+            );
+            return ExecuteResult::ExecuteSuccess;
+        }
+    }
+
+    return ExecuteResult::ExecuteTableUndefined;
+}
+
+pub fn prepare_aggregate(
+    input_parsed: &[Token],
+    statement: &mut Statement,
+    database: &mut Database,
+) -> PrepareResult {
+    if input_parsed.len() < 2 {
+        return PrepareResult::PrepareMissingCollection;
+    }
+
+    let collection_name = input_parsed[1].get_text();
+    match get_collection(statement, database, collection_name) {
+        CollectionResult::CollectionDoesntExist => {
+            return PrepareResult::PrepareCollectionDoesntExist
+        }
+        CollectionResult::CollectionSuccess => {}
+    }
+
+    let arguments = &input_parsed[2..];
+
+    // The stages can be written one after the other or inside a single array
+    let mut stages: Vec<Document> = Vec::new();
+    for argument in arguments.iter() {
+        match token_to_bson(argument) {
+            Ok(Bson::Document(stage)) => stages.push(stage),
+            Ok(Bson::Array(items)) if arguments.len() == 1 => {
+                for item in items {
+                    match item {
+                        Bson::Document(stage) => stages.push(stage),
+                        _ => {
+                            return PrepareResult::PrepareInvalidPipeline(
+                                "Each stage must be an object".to_string(),
+                            )
+                        }
+                    }
+                }
+            }
+            Ok(_) => return PrepareResult::PrepareSyntaxError,
+            Err(err) => return PrepareResult::PrepareCantParseJson(err),
+        }
+    }
+
+    if let Err(err) = validate_pipeline(&stages) {
+        return PrepareResult::PrepareInvalidPipeline(err);
+    }
+
+    statement.set_type(StatementType::StatementAggregate);
+    statement.set_pipeline(stages);
+    return PrepareResult::PrepareSuccess;
+}
+
+pub fn execute_aggregate(
+    statement: Statement,
+    database: &mut Database,
+    settings: &Settings,
+) -> ExecuteResult {
+    match database.find_collection(&statement.get_collection()) {
+        Some(collection) => {
+            let documents = match run_pipeline(
+                collection.get_collection().clone(),
+                statement.get_pipeline(),
+                database,
+            ) {
+                Ok(documents) => documents,
+                Err(err) => return ExecuteResult::ExecutePipelineFailed(err),
+            };
+            for doc in documents.iter() {
+                println!("{}", format_document(doc, settings.get_output_mode()));
+            }
+            return ExecuteResult::ExecuteSuccess;
+        }
+        None => return ExecuteResult::ExecuteTableUndefined,
+    }
+}
+
+pub fn prepare_create_index(
+    input_parsed: &[Token],
+    statement: &mut Statement,
+    database: &mut Database,
+) -> PrepareResult {
+    if input_parsed.len() < 2 {
+        return PrepareResult::PrepareMissingCollection;
+    }
+
+    let collection_name = input_parsed[1].get_text();
+    match get_collection(statement, database, collection_name) {
+        CollectionResult::CollectionDoesntExist => {
+            return PrepareResult::PrepareCollectionDoesntExist
+        }
+        CollectionResult::CollectionSuccess => {}
+    }
+
+    statement.set_type(StatementType::StatementCreateIndex);
+    let arguments = &input_parsed[2..];
+    if arguments.is_empty() || arguments.len() > 2 {
+        return PrepareResult::PrepareSyntaxError;
+    }
+
+    let mut documents: Vec<Document> = Vec::new();
+    for argument in arguments.iter() {
+        match token_to_document(argument) {
+            Ok(document) => documents.push(document),
+            Err(err) => return PrepareResult::PrepareCantParseJson(err),
+        }
+    }
+
+    if let Err(err) = validate_index_keys(&documents[0]) {
+        return PrepareResult::PrepareInvalidIndex(err);
+    }
+    if let Some(options) = documents.get(1) {
+        if let Err(err) = validate_index_options(options) {
+            return PrepareResult::PrepareInvalidIndex(err);
+        }
+        statement.set_index_options(options.clone());
+    }
+    statement.set_row_to_insert(documents.remove(0));
+    return PrepareResult::PrepareSuccess;
+}
+
+pub fn execute_create_index(statement: Statement, database: &mut Database) -> ExecuteResult {
+    for item in database.get_collections().iter_mut() {
+        if item.get_name().eq(&statement.get_collection()) {
+            match item.create_index(
+                statement.get_row_to_insert(),
+                &statement.get_index_options(),
+            ) {
+                Ok(name) => println!("Created index {}", name),
+                Err(err) => return ExecuteResult::ExecuteIndexFailed(err),
+            }
+            return ExecuteResult::ExecuteSuccess;
+        }
+    }
+
+    return ExecuteResult::ExecuteTableUndefined;
+}
+
+/// The index to drop can be given by name or by its specification.
+pub fn prepare_drop_index(
+    input_parsed: &[Token],
+    statement: &mut Statement,
+    database: &mut Database,
+) -> PrepareResult {
+    if input_parsed.len() < 2 {
+        return PrepareResult::PrepareMissingCollection;
+    }
+
+    let collection_name = input_parsed[1].get_text();
+    match get_collection(statement, database, collection_name) {
+        CollectionResult::CollectionDoesntExist => {
+            return PrepareResult::PrepareCollectionDoesntExist
+        }
+        CollectionResult::CollectionSuccess => {}
+    }
+
+    if input_parsed.len() != 3 {
+        return PrepareResult::PrepareSyntaxError;
+    }
+
+    statement.set_type(StatementType::StatementDropIndex);
+    let input = &input_parsed[2];
+    if !input.is_json() {
+        statement.set_index_name(input.get_text().to_owned());
+        return PrepareResult::PrepareSuccess;
+    }
+
+    match token_to_document(input) {
+        Ok(document) => {
+            if let Err(err) = validate_index_keys(&document) {
+                return PrepareResult::PrepareInvalidIndex(err);
+            }
+            statement.set_index_name(index_name(&document));
+            return PrepareResult::PrepareSuccess;
+        }
+        Err(err) => {
+            return PrepareResult::PrepareCantParseJson(err);
+        }
+    }
+}
+
+pub fn execute_drop_index(statement: Statement, database: &mut Database) -> ExecuteResult {
+    for item in database.get_collections().iter_mut() {
+        if item.get_name().eq(&statement.get_collection()) {
+            if !item.drop_index(&statement.get_index_name()) {
+                let err = format!("The index '{}' doesnt exist", statement.get_index_name());
+                return ExecuteResult::ExecuteIndexFailed(err);
+            }
+            println!("Dropped index {}", statement.get_index_name());
+            return ExecuteResult::ExecuteSuccess;
+        }
+    }
+
+    return ExecuteResult::ExecuteTableUndefined;
+}
+
+/// Reads a json value of the statement. Quoted strings and words arent json.
+fn token_to_bson(token: &Token) -> Result<Bson, LexError> {
+    if !token.is_json() {
+        return Err(token.error_at(0, "Expected a json value".to_string()));
+    }
+
+    match string_to_bson(token.get_text()) {
+        Ok(value) => return Ok(value),
+        Err(err) => return Err(token.error_at(err.get_offset(), err.get_message())),
+    }
+}
+
+fn token_to_document(token: &Token) -> Result<Document, LexError> {
+    if !token.is_json() {
+        return Err(token.error_at(0, "Expected a json object".to_string()));
+    }
+
+    match string_to_document(token.get_text()) {
+        Ok(document) => return Ok(document),
+        Err(err) => return Err(token.error_at(err.get_offset(), err.get_message())),
+    }
+}
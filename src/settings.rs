@@ -0,0 +1,56 @@
+use std::path::{Path, PathBuf};
+
+use crate::{history::HistoryOptions, output::OutputMode};
+
+/// The options of the REPL, changed with dot commands, that apply to every statement.
+pub struct Settings {
+    output_mode: OutputMode,
+    timer: bool,
+    history: HistoryOptions,
+    /// The scripts being run by `.read`, the innermost last, so that a script
+    /// cant read itself again.
+    scripts: Vec<PathBuf>,
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Self {
+            output_mode: OutputMode::Shell,
+            timer: false,
+            history: HistoryOptions::from_env(),
+            scripts: Vec::new(),
+        }
+    }
+
+    pub fn get_history(&self) -> &HistoryOptions {
+        return &self.history;
+    }
+
+    pub fn get_output_mode(&self) -> OutputMode {
+        return self.output_mode;
+    }
+
+    pub fn set_output_mode(&mut self, output_mode: OutputMode) {
+        self.output_mode = output_mode;
+    }
+
+    pub fn get_timer(&self) -> bool {
+        return self.timer;
+    }
+
+    pub fn set_timer(&mut self, timer: bool) {
+        self.timer = timer;
+    }
+
+    pub fn is_reading_script(&self, path: &Path) -> bool {
+        return self.scripts.iter().any(|script| script == path);
+    }
+
+    pub fn push_script(&mut self, path: PathBuf) {
+        self.scripts.push(path);
+    }
+
+    pub fn pop_script(&mut self) {
+        self.scripts.pop();
+    }
+}
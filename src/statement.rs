@@ -1,9 +1,18 @@
 use bson::Document;
 
 use crate::{
-    commit_changes, queries::{
-        execute_create, execute_delete, execute_find, execute_insert, execute_peek, prepare_create, prepare_delete, prepare_find, prepare_insert
-    }, Database
+    commit_changes,
+    filter::Filter,
+    lexer::{lex_statement, LexError, Token},
+    queries::{
+        execute_aggregate, execute_count, execute_create, execute_create_index, execute_delete,
+        execute_distinct, execute_drop_index, execute_explain, execute_find, execute_insert,
+        execute_peek, execute_update, prepare_aggregate, prepare_count, prepare_create,
+        prepare_create_index, prepare_delete, prepare_distinct, prepare_drop_index,
+        prepare_explain, prepare_find, prepare_insert, prepare_update,
+    },
+    settings::Settings,
+    Database,
 };
 
 #[derive(Clone, Copy)]
@@ -15,6 +24,14 @@ pub enum StatementType {
     StatementPeek,
     StatementCommit,
     StatementDelete,
+    StatementUpdate,
+    StatementReplace,
+    StatementCount,
+    StatementDistinct,
+    StatementAggregate,
+    StatementCreateIndex,
+    StatementDropIndex,
+    StatementExplain,
 }
 
 pub enum ExecuteResult {
@@ -24,6 +41,11 @@ pub enum ExecuteResult {
     ExecuteTableUndefined,
     ExecuteCollectionAlreadyExists,
     ExecuteCantSaveDatabase,
+    ExecuteUpdateFailed(String),
+    ExecuteDuplicateId(String),
+    ExecuteIndexFailed(String),
+    ExecuteDuplicateKey(String),
+    ExecutePipelineFailed(String),
 }
 
 pub enum PrepareResult {
@@ -32,14 +54,33 @@ pub enum PrepareResult {
     PrepareSyntaxError,
     PrepareCollectionDoesntExist,
     PrepareMissingCollection,
-    PrepareCantParseJson,
+    PrepareCantParseJson(LexError),
+    PrepareInvalidFilter(String),
+    PrepareInvalidUpdate(String),
+    PrepareInvalidProjection(String),
+    PrepareInvalidSort(String),
+    PrepareInvalidPipeline(String),
+    PrepareInvalidIndex(String),
+    PrepareParseError(LexError),
 }
 
 pub struct Statement {
     x_type: StatementType,
     row_to_insert: Option<Document>,
+    filter: Filter,
     collection: String,
     collection_name: String,
+    update_document: Option<Document>,
+    multi: bool,
+    upsert: bool,
+    projection: Option<Document>,
+    sort: Option<Document>,
+    skip: usize,
+    limit: usize,
+    field: String,
+    pipeline: Vec<Document>,
+    index_name: String,
+    index_options: Option<Document>,
 }
 
 impl Statement {
@@ -47,8 +88,20 @@ impl Statement {
         Self {
             x_type: StatementType::StatementUninitialized,
             row_to_insert: None,
+            filter: Filter::default(),
             collection: String::new(),
             collection_name: String::new(),
+            update_document: None,
+            multi: false,
+            upsert: false,
+            projection: None,
+            sort: None,
+            skip: 0,
+            limit: 0,
+            field: String::new(),
+            pipeline: Vec::new(),
+            index_name: String::new(),
+            index_options: None,
         }
     }
 
@@ -86,12 +139,119 @@ impl Statement {
     pub fn set_row_to_insert(&mut self, row: Document) {
         self.row_to_insert = Some(row);
     }
+
+    pub fn get_filter(&self) -> &Filter {
+        return &self.filter;
+    }
+
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+    }
+
+    pub fn get_update_document(&self) -> Document {
+        match &self.update_document {
+            Some(doc) => return doc.to_owned(),
+            None => return Document::new(),
+        }
+    }
+
+    pub fn set_update_document(&mut self, update: Document) {
+        self.update_document = Some(update);
+    }
+
+    pub fn get_multi(&self) -> bool {
+        return self.multi;
+    }
+
+    pub fn set_multi(&mut self, multi: bool) {
+        self.multi = multi;
+    }
+
+    pub fn get_upsert(&self) -> bool {
+        return self.upsert;
+    }
+
+    pub fn set_upsert(&mut self, upsert: bool) {
+        self.upsert = upsert;
+    }
+
+    pub fn get_projection(&self) -> Option<&Document> {
+        return self.projection.as_ref();
+    }
+
+    pub fn set_projection(&mut self, projection: Document) {
+        self.projection = Some(projection);
+    }
+
+    pub fn get_sort(&self) -> Option<&Document> {
+        return self.sort.as_ref();
+    }
+
+    pub fn set_sort(&mut self, sort: Document) {
+        self.sort = Some(sort);
+    }
+
+    pub fn get_skip(&self) -> usize {
+        return self.skip;
+    }
+
+    pub fn set_skip(&mut self, skip: usize) {
+        self.skip = skip;
+    }
+
+    /// A limit of 0 means no limit.
+    pub fn get_limit(&self) -> usize {
+        return self.limit;
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    pub fn get_field(&self) -> String {
+        return self.field.to_owned();
+    }
+
+    pub fn set_field(&mut self, field: String) {
+        self.field = field;
+    }
+
+    pub fn get_pipeline(&self) -> &Vec<Document> {
+        return &self.pipeline;
+    }
+
+    pub fn set_pipeline(&mut self, pipeline: Vec<Document>) {
+        self.pipeline = pipeline;
+    }
+
+    pub fn get_index_name(&self) -> String {
+        return self.index_name.to_owned();
+    }
+
+    pub fn set_index_name(&mut self, index_name: String) {
+        self.index_name = index_name;
+    }
+
+    pub fn get_index_options(&self) -> Document {
+        match &self.index_options {
+            Some(doc) => return doc.to_owned(),
+            None => return Document::new(),
+        }
+    }
+
+    pub fn set_index_options(&mut self, options: Document) {
+        self.index_options = Some(options);
+    }
 }
 
-pub fn execute_statement(statement: Statement, database: &mut Database) -> ExecuteResult {
+pub fn execute_statement(
+    statement: Statement,
+    database: &mut Database,
+    settings: &Settings,
+) -> ExecuteResult {
     match &statement.get_type() {
         StatementType::StatementFind => {
-            return execute_find(statement, database);
+            return execute_find(statement, database, settings);
         }
         StatementType::StatementInsert => {
             return execute_insert(statement, database);
@@ -114,7 +274,28 @@ pub fn execute_statement(statement: Statement, database: &mut Database) -> Execu
         },
         StatementType::StatementDelete => {
             return execute_delete(statement, database);
-        },
+        }
+        StatementType::StatementUpdate | StatementType::StatementReplace => {
+            return execute_update(statement, database);
+        }
+        StatementType::StatementCount => {
+            return execute_count(statement, database);
+        }
+        StatementType::StatementDistinct => {
+            return execute_distinct(statement, database, settings);
+        }
+        StatementType::StatementAggregate => {
+            return execute_aggregate(statement, database, settings);
+        }
+        StatementType::StatementCreateIndex => {
+            return execute_create_index(statement, database);
+        }
+        StatementType::StatementDropIndex => {
+            return execute_drop_index(statement, database);
+        }
+        StatementType::StatementExplain => {
+            return execute_explain(statement, database, settings);
+        }
         StatementType::StatementUninitialized => {
             eprintln!("No statement ready for execution");
             return ExecuteResult::ExecuteFailed;
@@ -122,24 +303,49 @@ pub fn execute_statement(statement: Statement, database: &mut Database) -> Execu
     }
 }
 
+/// The words a statement can start with.
+pub const STATEMENT_KEYWORDS: [&str; 15] = [
+    "insert",
+    "find",
+    "create",
+    "peek",
+    "commit",
+    "delete",
+    "update",
+    "updateOne",
+    "replace",
+    "count",
+    "distinct",
+    "aggregate",
+    "createIndex",
+    "dropIndex",
+    "explain",
+];
+
 pub fn prepare_statement(
     input: &str,
     statement: &mut Statement,
     database: &mut Database,
 ) -> PrepareResult {
-    let input_parsed: Vec<&str> = input.split(' ').collect();
+    let input_parsed: Vec<Token> = match lex_statement(input) {
+        Ok(tokens) => tokens,
+        Err(err) => return PrepareResult::PrepareParseError(err),
+    };
 
-    let statement_input = input_parsed[0];
+    let statement_input = match input_parsed.first() {
+        Some(statement_input) => statement_input.get_text(),
+        None => return PrepareResult::PrepareUnrecognizedStatement,
+    };
 
     match statement_input {
         "insert" => {
-            return prepare_insert(input_parsed, statement, database);
+            return prepare_insert(&input_parsed, statement, database);
         }
         "find" => {
-            return prepare_find(input_parsed, statement, database);
+            return prepare_find(&input_parsed, statement, database);
         }
         "create" => {
-            return prepare_create(input_parsed, statement);
+            return prepare_create(&input_parsed, statement);
         }
         "peek" => {
             statement.set_type(StatementType::StatementPeek);
@@ -150,7 +356,37 @@ pub fn prepare_statement(
             return PrepareResult::PrepareSuccess;
         }
         "delete" => {
-            return prepare_delete(input_parsed, statement, database);
+            return prepare_delete(&input_parsed, statement, database);
+        }
+        "update" => {
+            let statement_type = StatementType::StatementUpdate;
+            return prepare_update(&input_parsed, statement, database, statement_type, true);
+        }
+        "updateOne" => {
+            let statement_type = StatementType::StatementUpdate;
+            return prepare_update(&input_parsed, statement, database, statement_type, false);
+        }
+        "replace" => {
+            let statement_type = StatementType::StatementReplace;
+            return prepare_update(&input_parsed, statement, database, statement_type, false);
+        }
+        "count" => {
+            return prepare_count(&input_parsed, statement, database);
+        }
+        "distinct" => {
+            return prepare_distinct(&input_parsed, statement, database);
+        }
+        "aggregate" => {
+            return prepare_aggregate(&input_parsed, statement, database);
+        }
+        "createIndex" => {
+            return prepare_create_index(&input_parsed, statement, database);
+        }
+        "dropIndex" => {
+            return prepare_drop_index(&input_parsed, statement, database);
+        }
+        "explain" => {
+            return prepare_explain(&input_parsed, statement, database);
         }
         _ => {
             return PrepareResult::PrepareUnrecognizedStatement;
@@ -0,0 +1,359 @@
+use std::collections::BTreeMap;
+
+use bson::{Bson, Document};
+use serde::{Deserialize, Serialize};
+
+use crate::filter::get_path_values;
+
+/// Common English words that are left out of text indexes and searches.
+const STOP_WORDS: [&str; 48] = [
+    "a", "about", "an", "and", "are", "as", "at", "be", "been", "but", "by", "can", "did", "do",
+    "does", "for", "from", "had", "has", "have", "he", "her", "his", "i", "if", "in", "into", "is",
+    "it", "its", "me", "my", "no", "not", "of", "on", "or", "she", "so", "that", "the", "their",
+    "them", "they", "this", "to", "was", "with",
+];
+
+/// Returns true when every field of the index specification is "text".
+pub fn is_text_index(keys: &Document) -> bool {
+    return !keys.is_empty() && keys.values().all(|value| value.as_str() == Some("text"));
+}
+
+/// Splits the text into lowercase words, without stop words and reduced to
+/// their stem, so "Running dogs" gives "run" and "dog".
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = Vec::new();
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        if word.is_empty() {
+            continue;
+        }
+        let word = word.to_lowercase();
+        if STOP_WORDS.contains(&word.as_str()) {
+            continue;
+        }
+        tokens.push(stem(&word));
+    }
+
+    return tokens;
+}
+
+/// Strips the most common English suffixes. Stems shorter than three letters
+/// are not reduced, so "used" and "is" stay as they are.
+fn stem(word: &str) -> String {
+    const SUFFIXES: [(&str, &str); 8] = [
+        ("sses", "ss"),
+        ("ies", "y"),
+        ("ing", ""),
+        ("edly", ""),
+        ("ed", ""),
+        ("ly", ""),
+        ("ss", "ss"),
+        ("s", ""),
+    ];
+
+    for (suffix, replacement) in SUFFIXES {
+        let stem = match word.strip_suffix(suffix) {
+            Some(stem) => stem,
+            None => continue,
+        };
+        if stem.chars().count() < 3 || stem.ends_with('u') {
+            return word.to_string();
+        }
+
+        let mut stem = format!("{}{}", stem, replacement);
+        // "running" -> "runn" -> "run"
+        if suffix == "ing" || suffix == "ed" {
+            let mut chars = stem.chars().rev();
+            if let (Some(last), Some(before)) = (chars.next(), chars.next()) {
+                if last == before && !"lsz".contains(last) && last.is_alphabetic() {
+                    stem.pop();
+                }
+            }
+        }
+        return stem;
+    }
+
+    return word.to_string();
+}
+
+/// The parts of a `$search` string: words to look for, "quoted phrases" every
+/// result must contain and -words no result can contain.
+pub struct TextSearch {
+    terms: Vec<String>,
+    phrases: Vec<String>,
+    excluded: Vec<String>,
+}
+
+/// Checks the operand of `$text`, which needs a `$search` string.
+pub fn validate_text_search(operand: &Bson) -> Result<(), String> {
+    let operand = match operand {
+        Bson::Document(operand) => operand,
+        _ => return Err("'$text' needs an object with '$search'".to_string()),
+    };
+
+    for (key, value) in operand.iter() {
+        match (key.as_str(), value) {
+            ("$search", Bson::String(_)) => {}
+            ("$search", _) => return Err("'$search' needs a string".to_string()),
+            _ => return Err(format!("Unknown '$text' option '{}'", key)),
+        }
+    }
+    if !operand.contains_key("$search") {
+        return Err("'$text' needs '$search'".to_string());
+    }
+
+    return Ok(());
+}
+
+impl TextSearch {
+    /// Reads the search of a `$text` condition. The condition has to be valid.
+    pub fn parse(operand: &Bson) -> Self {
+        let search = match operand {
+            Bson::Document(operand) => operand.get_str("$search").unwrap_or(""),
+            _ => "",
+        };
+
+        let mut terms: Vec<String> = Vec::new();
+        let mut phrases: Vec<String> = Vec::new();
+        let mut excluded: Vec<String> = Vec::new();
+        for (i, part) in search.split('"').enumerate() {
+            // Odd parts are between quotes
+            if i % 2 == 1 {
+                phrases.push(part.to_lowercase());
+                terms.extend(tokenize(part));
+                continue;
+            }
+            for word in part.split_whitespace() {
+                match word.strip_prefix('-') {
+                    Some(word) => excluded.extend(tokenize(word)),
+                    None => terms.extend(tokenize(word)),
+                }
+            }
+        }
+        terms.sort();
+        terms.dedup();
+
+        return Self {
+            terms,
+            phrases,
+            excluded,
+        };
+    }
+
+    pub fn get_terms(&self) -> &Vec<String> {
+        return &self.terms;
+    }
+}
+
+/// An index of the words of one or more string fields. Like the ordered
+/// indexes, only the definition is saved and the entries are rebuilt on open.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TextIndex {
+    name: String,
+    keys: Document,
+    #[serde(skip)]
+    entries: BTreeMap<String, Vec<usize>>,
+}
+
+impl TextIndex {
+    pub fn new(name: String, keys: Document) -> Self {
+        Self {
+            name,
+            keys,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn get_name(&self) -> String {
+        return self.name.to_owned();
+    }
+
+    pub fn rebuild(&mut self, documents: &[Document]) {
+        self.entries.clear();
+        for (position, doc) in documents.iter().enumerate() {
+            self.add_document(doc, position);
+        }
+    }
+
+    pub fn add_document(&mut self, doc: &Document, position: usize) {
+        for token in self.document_tokens(doc) {
+            let positions = self.entries.entry(token).or_default();
+            if !positions.contains(&position) {
+                positions.push(position);
+            }
+        }
+    }
+
+    pub fn remove_document(&mut self, doc: &Document, position: usize) {
+        for token in self.document_tokens(doc) {
+            if let Some(positions) = self.entries.get_mut(&token) {
+                positions.retain(|item| *item != position);
+                if positions.is_empty() {
+                    self.entries.remove(&token);
+                }
+            }
+        }
+    }
+
+    /// Returns the strings of the indexed fields, including the strings inside arrays.
+    fn field_texts<'a>(&self, doc: &'a Document) -> Vec<Vec<&'a str>> {
+        let mut fields: Vec<Vec<&str>> = Vec::new();
+        for field in self.keys.keys() {
+            let mut texts: Vec<&str> = Vec::new();
+            for value in get_path_values(doc, field) {
+                match value {
+                    Bson::String(text) => texts.push(text),
+                    Bson::Array(items) => {
+                        texts.extend(items.iter().filter_map(|item| item.as_str()))
+                    }
+                    _ => {}
+                }
+            }
+            fields.push(texts);
+        }
+
+        return fields;
+    }
+
+    fn document_tokens(&self, doc: &Document) -> Vec<String> {
+        let mut tokens: Vec<String> = Vec::new();
+        for texts in self.field_texts(doc) {
+            for text in texts {
+                tokens.extend(tokenize(text));
+            }
+        }
+
+        return tokens;
+    }
+
+    /// Returns the positions of the documents matching the search, from the
+    /// highest score to the lowest.
+    pub fn search(&self, documents: &[Document], search: &TextSearch) -> Vec<usize> {
+        let mut positions: Vec<usize> = Vec::new();
+        for term in search.terms.iter() {
+            if let Some(items) = self.entries.get(term) {
+                positions.extend(items.iter());
+            }
+        }
+        positions.sort_unstable();
+        positions.dedup();
+
+        let mut scored: Vec<(usize, f64)> = positions
+            .into_iter()
+            .filter(|position| self.matches(&documents[*position], search))
+            .map(|position| (position, self.score(&documents[position], search)))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        return scored
+            .into_iter()
+            .map(|(position, _score)| position)
+            .collect();
+    }
+
+    fn matches(&self, doc: &Document, search: &TextSearch) -> bool {
+        let texts: Vec<String> = self
+            .field_texts(doc)
+            .into_iter()
+            .flatten()
+            .map(|text| text.to_lowercase())
+            .collect();
+        for phrase in search.phrases.iter() {
+            if !texts.iter().any(|text| text.contains(phrase.as_str())) {
+                return false;
+            }
+        }
+
+        let tokens = self.document_tokens(doc);
+        return !search.excluded.iter().any(|word| tokens.contains(word));
+    }
+
+    /// Scores the document for the search. In every field each occurrence of a
+    /// search term counts, relative to the length of the field, so short
+    /// fields that repeat the terms rank first.
+    pub fn score(&self, doc: &Document, search: &TextSearch) -> f64 {
+        let mut score = 0.0;
+        for texts in self.field_texts(doc) {
+            let tokens: Vec<String> = texts.into_iter().flat_map(tokenize).collect();
+            let occurrences = tokens
+                .iter()
+                .filter(|token| search.terms.contains(token))
+                .count();
+            if occurrences > 0 {
+                score += occurrences as f64 / (0.5 + 0.5 * tokens.len() as f64);
+            }
+        }
+
+        return score;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, Bson, Document};
+
+    use super::{tokenize, validate_text_search, TextIndex, TextSearch};
+
+    fn search(text: &str) -> TextSearch {
+        return TextSearch::parse(&Bson::Document(doc! { "$search": text }));
+    }
+
+    fn index_of(documents: &[Document]) -> TextIndex {
+        let mut index = TextIndex::new("body_text".to_string(), doc! { "body": "text" });
+        index.rebuild(documents);
+        return index;
+    }
+
+    #[test]
+    fn tokenizes_and_stems_words() {
+        assert_eq!(tokenize("Running dogs"), vec!["run", "dog"]);
+        assert_eq!(tokenize("The cat is in the box!"), vec!["cat", "box"]);
+        assert_eq!(
+            tokenize("classes studies jumped"),
+            vec!["class", "study", "jump"]
+        );
+        assert_eq!(tokenize("used bus"), vec!["used", "bus"]);
+        assert_eq!(tokenize(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn validates_searches() {
+        assert!(validate_text_search(&Bson::Document(doc! { "$search": "a" })).is_ok());
+        assert!(validate_text_search(&Bson::Document(doc! { "$search": 1 })).is_err());
+        assert!(validate_text_search(&Bson::Document(doc! {})).is_err());
+        let unknown = doc! { "$search": "a", "$language": "en" };
+        assert!(validate_text_search(&Bson::Document(unknown)).is_err());
+        assert!(validate_text_search(&Bson::String("a".to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_phrases_and_exclusions() {
+        let parsed = search("dogs \"lazy Fox\" -cats");
+        assert_eq!(parsed.get_terms(), &vec!["dog", "fox", "lazy"]);
+        assert_eq!(parsed.phrases, vec!["lazy fox"]);
+        assert_eq!(parsed.excluded, vec!["cat"]);
+    }
+
+    #[test]
+    fn finds_documents_by_score() {
+        let documents = vec![
+            doc! { "body": "a long story about a dog, a cat, a bird and a horse" },
+            doc! { "body": "dogs and dogs" },
+            doc! { "body": "only cats here" },
+            doc! { "body": ["the lazy fox", "jumped over the dog"] },
+            doc! { "title": "dog" },
+        ];
+        let index = index_of(&documents);
+
+        assert_eq!(index.search(&documents, &search("dog")), vec![1, 3, 0]);
+        assert_eq!(index.search(&documents, &search("dog -cat")), vec![1, 3]);
+        assert_eq!(
+            index.search(&documents, &search("dog \"lazy fox\"")),
+            vec![3]
+        );
+        assert_eq!(
+            index.search(&documents, &search("whale")),
+            Vec::<usize>::new()
+        );
+        assert_eq!(index.score(&documents[4], &search("dog")), 0.0);
+    }
+}
@@ -0,0 +1,504 @@
+use bson::{Bson, Document};
+
+use crate::{
+    filter::{as_integer, as_number, matches_filter, matches_value},
+    ordering::values_equal,
+};
+
+const UPDATE_OPERATORS: [&str; 8] = [
+    "$set",
+    "$unset",
+    "$inc",
+    "$mul",
+    "$rename",
+    "$push",
+    "$pull",
+    "$addToSet",
+];
+
+/// How many nulls setting an index past the end of an array can add, like mongo.
+const MAX_ARRAY_PADDING: usize = 1500000;
+
+/// Checks that the update document only uses known update operators, each one
+/// with an object of fields as operand.
+pub fn validate_update(update: &Document) -> Result<(), String> {
+    if update.is_empty() {
+        return Err("The update document is empty".to_string());
+    }
+
+    for (operator, operand) in update.iter() {
+        if !UPDATE_OPERATORS.contains(&operator.as_str()) {
+            return Err(format!("Unknown update operator '{}'", operator));
+        }
+        let fields = match operand {
+            Bson::Document(fields) => fields,
+            _ => return Err(format!("'{}' needs an object of fields", operator)),
+        };
+        for (field, value) in fields.iter() {
+            match operator.as_str() {
+                "$inc" | "$mul" if as_number(value).is_none() => {
+                    return Err(format!("'{}' needs a number for '{}'", operator, field));
+                }
+                "$rename" if !matches!(value, Bson::String(_)) => {
+                    return Err(format!("'$rename' needs a new field name for '{}'", field));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+/// Checks that a replacement document is a plain document, without update operators.
+pub fn validate_replacement(replacement: &Document) -> Result<(), String> {
+    for key in replacement.keys() {
+        if key.starts_with('$') {
+            return Err(format!(
+                "A replacement document cant use the operator '{}'",
+                key
+            ));
+        }
+    }
+
+    return Ok(());
+}
+
+/// Builds the document inserted by an upsert from the equality conditions of the filter.
+pub fn document_from_filter(filter: &Document) -> Document {
+    let mut doc = Document::new();
+    add_filter_equalities(&mut doc, filter);
+    return doc;
+}
+
+fn add_filter_equalities(doc: &mut Document, filter: &Document) {
+    for (key, condition) in filter.iter() {
+        if key == "$and" {
+            if let Bson::Array(filters) = condition {
+                for inner in filters.iter().filter_map(|inner| inner.as_document()) {
+                    add_filter_equalities(doc, inner);
+                }
+            }
+            continue;
+        }
+        if key.starts_with('$') {
+            continue;
+        }
+        let value = match condition {
+            Bson::Document(operators) if operators.keys().any(|op| op.starts_with('$')) => {
+                match operators.get("$eq") {
+                    Some(value) => value.clone(),
+                    None => continue,
+                }
+            }
+            _ => condition.clone(),
+        };
+        let _ = set_path(doc, key, value);
+    }
+}
+
+/// Applies every operator of the update document to the document. On error the
+/// document may be partially modified, so callers should work on a copy.
+pub fn apply_update(doc: &mut Document, update: &Document) -> Result<(), String> {
+    for (operator, operand) in update.iter() {
+        let fields = match operand {
+            Bson::Document(fields) => fields,
+            _ => return Err(format!("'{}' needs an object of fields", operator)),
+        };
+        for (path, value) in fields.iter() {
+            match operator.as_str() {
+                "$set" => set_path(doc, path, value.clone())?,
+                "$unset" => {
+                    remove_path(doc, path);
+                }
+                "$inc" => apply_arithmetic(doc, path, value, Arithmetic::Add)?,
+                "$mul" => apply_arithmetic(doc, path, value, Arithmetic::Multiply)?,
+                "$rename" => apply_rename(doc, path, value)?,
+                "$push" => apply_push(doc, path, value, false)?,
+                "$addToSet" => apply_push(doc, path, value, true)?,
+                "$pull" => apply_pull(doc, path, value)?,
+                _ => return Err(format!("Unknown update operator '{}'", operator)),
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+#[derive(Clone, Copy)]
+enum Arithmetic {
+    Add,
+    Multiply,
+}
+
+fn apply_arithmetic(
+    doc: &mut Document,
+    path: &str,
+    operand: &Bson,
+    arithmetic: Arithmetic,
+) -> Result<(), String> {
+    let current = match get_path(doc, path) {
+        Some(current) => current.clone(),
+        None => match arithmetic {
+            Arithmetic::Add => Bson::Int32(0),
+            Arithmetic::Multiply => {
+                let zero = compute(&Bson::Int32(0), operand, Arithmetic::Multiply);
+                return set_path(doc, path, zero.unwrap_or(Bson::Int32(0)));
+            }
+        },
+    };
+
+    match compute(&current, operand, arithmetic) {
+        Some(result) => return set_path(doc, path, result),
+        None => {
+            return Err(format!(
+                "Cant apply arithmetic to non numeric field '{}'",
+                path
+            ))
+        }
+    }
+}
+
+fn compute(current: &Bson, operand: &Bson, arithmetic: Arithmetic) -> Option<Bson> {
+    match (current, operand) {
+        (Bson::Int32(a), Bson::Int32(b)) => {
+            let result = match arithmetic {
+                Arithmetic::Add => a.checked_add(*b),
+                Arithmetic::Multiply => a.checked_mul(*b),
+            };
+            match result {
+                Some(result) => return Some(Bson::Int32(result)),
+                None => return compute(&Bson::Int64(*a as i64), operand, arithmetic),
+            }
+        }
+        (Bson::Int32(_) | Bson::Int64(_), Bson::Int32(_) | Bson::Int64(_)) => {
+            let a = as_integer(current)?;
+            let b = as_integer(operand)?;
+            let result = match arithmetic {
+                Arithmetic::Add => a.checked_add(b),
+                Arithmetic::Multiply => a.checked_mul(b),
+            };
+            match result {
+                Some(result) => return Some(Bson::Int64(result)),
+                None => return compute(&Bson::Double(a as f64), operand, arithmetic),
+            }
+        }
+        _ => {
+            let a = as_number(current)?;
+            let b = as_number(operand)?;
+            match arithmetic {
+                Arithmetic::Add => return Some(Bson::Double(a + b)),
+                Arithmetic::Multiply => return Some(Bson::Double(a * b)),
+            }
+        }
+    }
+}
+
+fn apply_rename(doc: &mut Document, path: &str, new_path: &Bson) -> Result<(), String> {
+    let new_path = match new_path {
+        Bson::String(new_path) => new_path,
+        _ => return Err(format!("'$rename' needs a new field name for '{}'", path)),
+    };
+
+    if let Some(value) = remove_path(doc, path) {
+        set_path(doc, new_path, value)?;
+    }
+
+    return Ok(());
+}
+
+fn apply_push(doc: &mut Document, path: &str, value: &Bson, unique: bool) -> Result<(), String> {
+    let values: Vec<Bson> = match value {
+        Bson::Document(modifiers) if modifiers.contains_key("$each") => {
+            match modifiers.get("$each") {
+                Some(Bson::Array(each)) => each.clone(),
+                _ => return Err("'$each' needs an array".to_string()),
+            }
+        }
+        _ => vec![value.clone()],
+    };
+
+    let mut items = match get_path(doc, path) {
+        Some(Bson::Array(items)) => items.clone(),
+        Some(_) => return Err(format!("Cant push to non array field '{}'", path)),
+        None => Vec::new(),
+    };
+
+    for value in values {
+        if unique && items.iter().any(|item| values_equal(item, &value)) {
+            continue;
+        }
+        items.push(value);
+    }
+
+    return set_path(doc, path, Bson::Array(items));
+}
+
+fn apply_pull(doc: &mut Document, path: &str, condition: &Bson) -> Result<(), String> {
+    let mut items = match get_path(doc, path) {
+        Some(Bson::Array(items)) => items.clone(),
+        Some(_) => return Err(format!("Cant pull from non array field '{}'", path)),
+        None => return Ok(()),
+    };
+
+    items.retain(|item| match (item, condition) {
+        (Bson::Document(item), Bson::Document(filter))
+            if !filter.keys().any(|key| key.starts_with('$')) =>
+        {
+            !matches_filter(item, filter)
+        }
+        _ => !matches_value(item, condition),
+    });
+
+    return set_path(doc, path, Bson::Array(items));
+}
+
+/// Returns the value at a dotted path, following numeric indexes into arrays.
+pub fn get_path<'a>(doc: &'a Document, path: &str) -> Option<&'a Bson> {
+    let mut parts = path.split('.');
+    let mut current = doc.get(parts.next()?)?;
+
+    for part in parts {
+        current = match current {
+            Bson::Document(inner) => inner.get(part)?,
+            Bson::Array(items) => items.get(part.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+
+    return Some(current);
+}
+
+/// Sets the value at a dotted path, creating the missing embedded documents.
+pub fn set_path(doc: &mut Document, path: &str, value: Bson) -> Result<(), String> {
+    match path.split_once('.') {
+        None => {
+            doc.insert(path, value);
+            return Ok(());
+        }
+        Some((head, rest)) => {
+            if !doc.contains_key(head) {
+                doc.insert(head, Document::new());
+            }
+            match doc.get_mut(head) {
+                Some(child) => return set_value_path(child, rest, value, path),
+                None => return Err(format!("Cant set field '{}'", path)),
+            }
+        }
+    }
+}
+
+fn set_value_path(
+    target: &mut Bson,
+    path: &str,
+    value: Bson,
+    full_path: &str,
+) -> Result<(), String> {
+    match target {
+        Bson::Document(inner) => return set_path(inner, path, value),
+        Bson::Array(items) => {
+            let (head, rest) = match path.split_once('.') {
+                Some((head, rest)) => (head, Some(rest)),
+                None => (path, None),
+            };
+            let index = match head.parse::<usize>() {
+                Ok(index) => index,
+                Err(_e) => {
+                    return Err(format!(
+                        "Cant use '{}' as an array index in '{}'",
+                        head, full_path
+                    ))
+                }
+            };
+            if index.saturating_sub(items.len()) > MAX_ARRAY_PADDING {
+                return Err(format!(
+                    "Cant add more than {} nulls to an array to set '{}'",
+                    MAX_ARRAY_PADDING, full_path
+                ));
+            }
+            while items.len() <= index {
+                items.push(Bson::Null);
+            }
+            match rest {
+                Some(rest) => {
+                    if items[index] == Bson::Null {
+                        items[index] = Bson::Document(Document::new());
+                    }
+                    return set_value_path(&mut items[index], rest, value, full_path);
+                }
+                None => {
+                    items[index] = value;
+                    return Ok(());
+                }
+            }
+        }
+        _ => {
+            return Err(format!(
+                "Cant create field '{}' inside a non object value",
+                full_path
+            ))
+        }
+    }
+}
+
+/// Removes the value at a dotted path and returns it. Array elements are set
+/// to null instead of removed, so the positions of the others dont change.
+pub fn remove_path(doc: &mut Document, path: &str) -> Option<Bson> {
+    match path.split_once('.') {
+        None => return doc.remove(path),
+        Some((head, rest)) => return remove_value_path(doc.get_mut(head)?, rest),
+    }
+}
+
+fn remove_value_path(target: &mut Bson, path: &str) -> Option<Bson> {
+    match target {
+        Bson::Document(inner) => return remove_path(inner, path),
+        Bson::Array(items) => {
+            let (head, rest) = match path.split_once('.') {
+                Some((head, rest)) => (head, Some(rest)),
+                None => (path, None),
+            };
+            let item = items.get_mut(head.parse::<usize>().ok()?)?;
+            match rest {
+                Some(rest) => return remove_value_path(item, rest),
+                None => return Some(std::mem::replace(item, Bson::Null)),
+            }
+        }
+        _ => return None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, Bson, Document};
+
+    use super::{
+        apply_update, document_from_filter, validate_replacement, validate_update,
+        MAX_ARRAY_PADDING,
+    };
+
+    fn updated(doc: Document, update: Document) -> Document {
+        let mut doc = doc;
+        apply_update(&mut doc, &update).unwrap();
+        return doc;
+    }
+
+    #[test]
+    fn sets_and_unsets_fields() {
+        let doc = doc! { "name": "Ada", "age": 36 };
+        let doc = updated(
+            doc,
+            doc! { "$set": { "address.city": "London", "age": 37 } },
+        );
+        assert_eq!(
+            doc,
+            doc! { "name": "Ada", "age": 37, "address": { "city": "London" } }
+        );
+        let doc = updated(doc, doc! { "$unset": { "name": "", "address.city": "" } });
+        assert_eq!(doc, doc! { "age": 37, "address": {} });
+    }
+
+    #[test]
+    fn sets_array_elements() {
+        let doc = updated(doc! { "tags": ["a"] }, doc! { "$set": { "tags.2": "c" } });
+        assert_eq!(doc, doc! { "tags": ["a", null, "c"] });
+        let doc = updated(doc, doc! { "$unset": { "tags.0": "" } });
+        assert_eq!(doc, doc! { "tags": [null, null, "c"] });
+    }
+
+    #[test]
+    fn refuses_to_pad_arrays_too_far() {
+        let mut doc = doc! { "tags": [] };
+        let path = format!("tags.{}", MAX_ARRAY_PADDING + 1);
+        assert!(apply_update(&mut doc, &doc! { "$set": { path: 1 } }).is_err());
+        let path = format!("tags.{}", usize::MAX);
+        assert!(apply_update(&mut doc, &doc! { "$set": { path: 1 } }).is_err());
+    }
+
+    #[test]
+    fn applies_arithmetic() {
+        let doc = doc! { "count": 1, "price": 2.5, "big": i32::MAX };
+        let update =
+            doc! { "$inc": { "count": 2, "missing": 5, "big": 1 }, "$mul": { "price": 2 } };
+        let doc = updated(doc, update);
+        assert_eq!(doc.get("count"), Some(&Bson::Int32(3)));
+        assert_eq!(doc.get("missing"), Some(&Bson::Int32(5)));
+        assert_eq!(doc.get("big"), Some(&Bson::Int64(i32::MAX as i64 + 1)));
+        assert_eq!(doc.get("price"), Some(&Bson::Double(5.0)));
+
+        let doc = updated(doc! {}, doc! { "$mul": { "missing": 3 } });
+        assert_eq!(doc.get("missing"), Some(&Bson::Int32(0)));
+
+        let mut doc = doc! { "name": "Ada" };
+        assert!(apply_update(&mut doc, &doc! { "$inc": { "name": 1 } }).is_err());
+    }
+
+    #[test]
+    fn renames_fields() {
+        let doc = updated(
+            doc! { "a": 1, "b": { "c": 2 } },
+            doc! { "$rename": { "b.c": "d" } },
+        );
+        assert_eq!(doc, doc! { "a": 1, "b": {}, "d": 2 });
+    }
+
+    #[test]
+    fn pushes_and_pulls() {
+        let doc = doc! { "tags": ["a", "b"], "scores": [1, 5, 9] };
+        let doc = updated(doc, doc! { "$push": { "tags": "b", "new": 1 } });
+        assert_eq!(doc.get_array("tags").unwrap().len(), 3);
+        assert_eq!(doc.get_array("new").unwrap(), &vec![Bson::Int32(1)]);
+
+        let doc = updated(
+            doc,
+            doc! { "$addToSet": { "tags": { "$each": ["a", "c"] } } },
+        );
+        assert_eq!(doc.get_array("tags").unwrap().len(), 4);
+
+        let doc = updated(
+            doc,
+            doc! { "$pull": { "tags": "b", "scores": { "$gte": 5 } } },
+        );
+        assert_eq!(doc.get("tags"), Some(&Bson::from(vec!["a", "c"])));
+        assert_eq!(doc.get("scores"), Some(&Bson::from(vec![1])));
+
+        let mut doc = doc! { "name": "Ada" };
+        assert!(apply_update(&mut doc, &doc! { "$push": { "name": 1 } }).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_updates() {
+        assert!(validate_update(&doc! {}).is_err());
+        assert!(validate_update(&doc! { "name": "Ada" }).is_err());
+        assert!(validate_update(&doc! { "$set": 1 }).is_err());
+        assert!(validate_update(&doc! { "$inc": { "age": "1" } }).is_err());
+        assert!(validate_update(&doc! { "$rename": { "a": 1 } }).is_err());
+        assert!(validate_update(&doc! { "$set": { "a": 1 }, "$inc": { "b": 1 } }).is_ok());
+    }
+
+    #[test]
+    fn builds_upserted_documents_from_filters() {
+        let filter = doc! {
+            "name": "Ada",
+            "address.city": "London",
+            "age": { "$gt": 30 },
+            "status": { "$eq": "active" },
+            "$and": [{ "team": "core" }],
+            "$or": [{ "x": 1 }, { "y": 2 }],
+        };
+        let expected = doc! {
+            "name": "Ada",
+            "address": { "city": "London" },
+            "status": "active",
+            "team": "core",
+        };
+        assert_eq!(document_from_filter(&filter), expected);
+    }
+
+    #[test]
+    fn rejects_replacements_with_operators() {
+        assert!(validate_replacement(&doc! { "$set": { "a": 1 } }).is_err());
+        assert!(validate_replacement(&doc! { "a": { "$gt": 1 } }).is_ok());
+        assert!(validate_replacement(&doc! {}).is_ok());
+    }
+}